@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use ropey::Rope;
+use spiral::{
+    buffer::Buffer,
+    engine::Size,
+    view::{View, ViewId, ViewMut},
+};
+
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+/// Rust source of roughly `lines` lines
+fn generate_source(lines: usize) -> String {
+    let mut source = String::new();
+    for i in 0..lines / 5 {
+        source.push_str(&format!(
+            "fn function_{i}(x: u32) -> u32 {{\n    let y = x * {i};\n    // add one\n    y + 1\n}}\n"
+        ));
+    }
+    source
+}
+
+type BenchView = ViewMut<HashMap<ViewId, View>>;
+
+fn setup(lines: usize) -> (Buffer, BenchView) {
+    let buffer =
+        Buffer::create_from_contents("bench.rs".into(), Rope::from(generate_source(lines)));
+    let view = View::new(
+        buffer.id,
+        Size {
+            width: 80,
+            height: 24,
+        },
+    );
+    let id = view.id;
+    (buffer, ViewMut::new(HashMap::from([(id, view)]), id))
+}
+
+/// Types a line in the middle of the buffer, highlighting after every keystroke
+/// as a draw would
+fn typing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("typing");
+    // A whole session per iteration is slow on the larger buffers
+    group.sample_size(10);
+    for lines in SIZES {
+        group.bench_with_input(BenchmarkId::from_parameter(lines), &lines, |b, &lines| {
+            b.iter_batched(
+                || setup(lines),
+                |(mut buffer, mut view)| {
+                    let start = buffer.contents.line_to_char(lines / 2);
+                    for (pos, c) in (start..).zip("let z = y;\n".chars()) {
+                        buffer.insert(&mut view, &c.to_string(), pos);
+                        buffer.recalc_tree();
+                        buffer.flush_highlight();
+                    }
+                    (buffer, view)
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+/// Inserts and removes a line without highlighting, measuring the rope and
+/// tree-sitter bookkeeping alone
+fn insert_remove(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_remove");
+    for lines in SIZES {
+        let (mut buffer, mut view) = setup(lines);
+        let pos = buffer.contents.line_to_char(lines / 2);
+        group.bench_function(BenchmarkId::from_parameter(lines), |b| {
+            b.iter(|| {
+                buffer.insert(&mut view, "let z = y;\n", pos);
+                buffer.remove(&mut view, pos, 11);
+            });
+        });
+    }
+    group.finish();
+}
+
+fn recalc_tree(c: &mut Criterion) {
+    let mut group = c.benchmark_group("recalc_tree");
+    for lines in SIZES {
+        let (mut buffer, mut view) = setup(lines);
+        let pos = buffer.contents.line_to_char(lines / 2);
+        group.bench_function(BenchmarkId::from_parameter(lines), |b| {
+            b.iter(|| {
+                buffer.insert(&mut view, "x", pos);
+                buffer.recalc_tree();
+                buffer.remove(&mut view, pos, 1);
+                buffer.recalc_tree();
+            });
+        });
+    }
+    group.finish();
+}
+
+fn highlight(c: &mut Criterion) {
+    let mut group = c.benchmark_group("highlight");
+    for lines in SIZES {
+        let (mut buffer, _) = setup(lines);
+        let mut colors = vec![];
+        group.bench_function(BenchmarkId::from_parameter(lines), |b| {
+            b.iter(|| {
+                buffer
+                    .highlighter
+                    .highlight(&buffer.contents, &mut colors)
+                    .unwrap()
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, typing, insert_remove, recalc_tree, highlight);
+criterion_main!(benches);
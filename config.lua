@@ -3,7 +3,12 @@
 
 Editor.bind("normal", ":", "enter-command-mode")
 Editor.bind("normal", "C-r", "reload-config")
-Editor.bind("normal", "i", "enter-mode insert")
+Editor.bind("normal", "C-q", "quit")
+Editor.bind("normal", "i", "insert-mode")
+Editor.bind("normal", "a", "append")
+Editor.bind("normal", "I", "block-insert")
+Editor.bind("normal", "o", "open-below")
+Editor.bind("normal", "O", "open-above")
 Editor.bind("normal", "h", "move-char-left")
 Editor.bind("normal", "j", "move-char-down")
 Editor.bind("normal", "k", "move-char-up")
@@ -19,12 +24,43 @@ Editor.bind("normal", "g l", "goto-end-of-line")
 Editor.bind("normal", "g G", "extend-start")
 Editor.bind("normal", "g E", "extend-end")
 Editor.bind("normal", "g H", "extend-start-of-line")
+Editor.bind("normal", "g s", "goto-line-start-smart")
+Editor.bind("normal", "g a", "alternate-buffer")
+Editor.bind("normal", "g S", "extend-line-start-smart")
+Editor.bind("normal", "home", "goto-line-start-smart")
 Editor.bind("normal", "g L", "extend-end-of-line")
+Editor.bind("normal", "f", "find-char")
+Editor.bind("normal", "t", "till-char")
+Editor.bind("normal", "A-f", "find-char-backward")
+Editor.bind("normal", "A-t", "till-char-backward")
+Editor.bind("normal", "F", "extend-find-char")
+Editor.bind("normal", "T", "extend-till-char")
+Editor.bind("normal", "A-F", "extend-find-char-backward")
+Editor.bind("normal", "A-T", "extend-till-char-backward")
+Editor.bind("normal", ";", "repeat-char-find")
+Editor.bind("normal", ",", "repeat-char-find-reverse")
+Editor.bind("normal", "A-,", "keep-primary-selection")
+Editor.bind("normal", "A-;", "collapse-selections")
+Editor.bind("normal", "m i", "select-inside")
+Editor.bind("normal", "m a", "select-around")
+Editor.bind("normal", "] p", "move-paragraph-forward")
+Editor.bind("normal", "[ p", "move-paragraph-backward")
+Editor.bind("normal", "] P", "extend-paragraph-forward")
+Editor.bind("normal", "[ P", "extend-paragraph-backward")
+Editor.bind("normal", "] c", "goto-next-change")
+Editor.bind("normal", "[ c", "goto-prev-change")
 Editor.bind("normal", "u", "undo")
 Editor.bind("normal", "U", "redo")
+Editor.bind("normal", "A-u", "selection-undo")
+Editor.bind("normal", "A-U", "selection-redo")
 Editor.bind("normal", "y", "copy-kill-ring")
 Editor.bind("normal", "p", "paste-kill-ring false")
 Editor.bind("normal", "P", "paste-kill-ring true")
+Editor.bind("normal", "/", "prompt / search")
+Editor.bind("normal", "n", "search-next")
+Editor.bind("normal", "N", "search-prev")
+Editor.bind("normal", ">", "indent")
+Editor.bind("normal", "<", "dedent")
 
 Editor.register_command("extend-selection-to-lines", "Extend current selection to entire lines", function()
     local view = Editor.get_active_view()
@@ -85,9 +121,15 @@ Editor.bind("file-tree", "j", "move-char-down", "extend-selection-to-lines")
 Editor.bind("file-tree", "k", "move-char-up", "extend-selection-to-lines")
 Editor.bind("file-tree", "enter", "file-tree-open-current")
 
+Editor.bind("insert", "C-q", "quit")
 Editor.bind("insert", "bspc", "backspace")
+Editor.bind("insert", "C-w", "delete-word-backward")
+Editor.bind("insert", "C-t", "transpose-chars")
+Editor.bind("insert", "C-k", "insert-digraph")
+Editor.bind("insert", "insert", "set-overwrite")
 Editor.bind("insert", "enter", "insert \"\\n\"")
-Editor.bind("insert", "tab", "insert \"\\t\"")
+Editor.bind("insert", "tab", "insert-indent")
+Editor.bind("insert", "home", "goto-line-start-smart")
 
 Editor.bind("normal", "A-o", "tree-sitter-out")
 Editor.bind("normal", "A-i", "tree-sitter-in")
@@ -1,6 +1,12 @@
 use std::{
     io::Write,
-    sync::atomic::{AtomicUsize, Ordering},
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use log::debug;
@@ -9,8 +15,14 @@ use ratatui::style::Color;
 use ropey::{Rope, RopeSlice};
 use tree_sitter::{InputEdit, Parser, Point, Tree};
 use tree_sitter_highlight::{HighlightConfiguration, HighlightEvent, Highlighter};
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
 
-use crate::view::View;
+use crate::{
+    git::{self, Hunk},
+    options::OptionOverrides,
+    sign::{SignGroup, Signs},
+    view::{ViewMut, Views},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct BufferId(pub usize);
@@ -32,89 +44,315 @@ impl<'lua> FromLua<'lua> for BufferId {
     }
 }
 
+/// Char index of the grapheme boundary after `char_idx`, so that motions
+/// don't land inside a cluster such as an accented letter or an emoji sequence
+pub fn next_grapheme_boundary(text: &Rope, char_idx: usize) -> usize {
+    if char_idx >= text.len_chars() {
+        return text.len_chars();
+    }
+    let byte_idx = text.char_to_byte(char_idx);
+    let (mut chunk, mut chunk_start, _, _) = text.chunk_at_byte(byte_idx);
+    let mut cursor = GraphemeCursor::new(byte_idx, text.len_bytes(), true);
+    loop {
+        match cursor.next_boundary(chunk, chunk_start) {
+            Ok(None) => return text.len_chars(),
+            Ok(Some(n)) => return text.byte_to_char(n),
+            Err(GraphemeIncomplete::NextChunk) => {
+                chunk_start += chunk.len();
+                chunk = text.chunk_at_byte(chunk_start).0;
+            }
+            Err(GraphemeIncomplete::PrevChunk) => {
+                let (prev, prev_start, _, _) = text.chunk_at_byte(chunk_start - 1);
+                chunk = prev;
+                chunk_start = prev_start;
+            }
+            Err(GraphemeIncomplete::PreContext(n)) => {
+                let (context, context_start, _, _) = text.chunk_at_byte(n - 1);
+                cursor.provide_context(context, context_start);
+            }
+            // The chunk doesn't hold the cursor, so fall back to the next char
+            Err(GraphemeIncomplete::InvalidOffset) => return char_idx + 1,
+        }
+    }
+}
+
+/// Char index of the grapheme boundary before `char_idx`
+pub fn prev_grapheme_boundary(text: &Rope, char_idx: usize) -> usize {
+    let char_idx = char_idx.min(text.len_chars());
+    if char_idx == 0 {
+        return 0;
+    }
+    let byte_idx = text.char_to_byte(char_idx);
+    let (mut chunk, mut chunk_start, _, _) = text.chunk_at_byte(byte_idx - 1);
+    let mut cursor = GraphemeCursor::new(byte_idx, text.len_bytes(), true);
+    loop {
+        match cursor.prev_boundary(chunk, chunk_start) {
+            Ok(None) => return 0,
+            Ok(Some(n)) => return text.byte_to_char(n),
+            Err(GraphemeIncomplete::PrevChunk) => {
+                let (prev, prev_start, _, _) = text.chunk_at_byte(chunk_start - 1);
+                chunk = prev;
+                chunk_start = prev_start;
+            }
+            Err(GraphemeIncomplete::NextChunk) => {
+                chunk_start += chunk.len();
+                chunk = text.chunk_at_byte(chunk_start).0;
+            }
+            Err(GraphemeIncomplete::PreContext(n)) => {
+                let (context, context_start, _, _) = text.chunk_at_byte(n - 1);
+                cursor.provide_context(context, context_start);
+            }
+            // The chunk doesn't hold the cursor, so fall back to the previous char
+            Err(GraphemeIncomplete::InvalidOffset) => return char_idx - 1,
+        }
+    }
+}
+
+/// Number of lines in `text`, where a trailing newline ends the last line
+/// rather than starting an empty one after it. Ropey counts that empty line,
+/// so `"a\n"` has 2 lines there and 1 here.
+pub fn line_count(text: &Rope) -> usize {
+    let lines = text.len_lines();
+    if lines > 1 && text.line(lines - 1).len_chars() == 0 {
+        lines - 1
+    } else {
+        lines
+    }
+}
+
+/// Guesses a buffer's language from the file extension
+pub fn detect_language(path: &Path) -> Option<&'static str> {
+    let language = match path.extension()?.to_str()? {
+        "rs" => "rust",
+        "lua" => "lua",
+        "toml" => "toml",
+        "md" => "markdown",
+        "py" => "python",
+        "c" | "h" => "c",
+        "js" => "javascript",
+        "json" => "json",
+        "sh" => "bash",
+        "txt" => "text",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// How long editing has to pause before the lines that aren't drawn are highlighted
+const HIGHLIGHT_DEBOUNCE: Duration = Duration::from_millis(30);
+/// How long editing has to pause before the git signs are recomputed
+const DIFF_DEBOUNCE: Duration = Duration::from_millis(500);
+
 pub struct Buffer {
     pub id: BufferId,
     pub name: String,
     pub view_count: usize,
     pub contents: ropey::Rope,
+    /// Bumped by every insertion and removal
+    pub version: u64,
+    /// `version` when the buffer was last loaded or saved
+    pub saved_version: u64,
+    /// Shown in the sign column of every view of this buffer
+    pub signs: Signs,
+    /// The file as known to git, which `diff_hunks` compares against
+    pub diff_base: Option<Vec<u8>>,
+    pub diff_hunks: Vec<Hunk>,
+    /// Time of the last edit not yet reflected in `diff_hunks`
+    pub undiffed_edit: Option<Instant>,
     pub history: History,
 
     pub backing: BufferBacking,
+    /// State of the backing file when it was last loaded or saved
+    pub disk_stamp: Option<DiskStamp>,
+    /// An outside change to the backing file that has already been warned about
+    pub disk_change_warned: Option<DiskStamp>,
 
+    pub language: String,
+    pub options: OptionOverrides,
     pub parser: Parser,
     pub tree: Tree,
     pub highlighter: HighlightCtx,
-
+    /// When set, highlighting happens on a background thread and `colors`
+    /// lags behind edits until the result arrives
+    pub highlight_worker: Option<HighlightWorker>,
+    /// Generation of the current text, bumped by every reparse
+    pub highlight_generation: u64,
+    /// Generation of the newest snapshot sent to the worker
+    pub highlight_requested: u64,
+    /// Generation of the snapshot `colors` was computed from
+    pub highlight_applied: u64,
+    /// Bytes highlighted ahead of the worker to be drawn, and their generation
+    pub visible_highlight: Option<(u64, Range<usize>)>,
+    /// Time of the last edit not yet highlighted
+    pub unhighlighted_edit: Option<Instant>,
+    /// Whether `tree` and `colors` are kept up to date. Off for large files.
+    pub syntax: bool,
+
+    /// Highlight color per byte; empty when `syntax` is off
     pub colors: Vec<Color>,
 }
 
 impl Buffer {
     pub fn create_from_contents(name: String, rope: Rope) -> Self {
-        let id = BufferId::generate();
+        let mut buffer = Self::create_without_syntax(name, rope);
+        buffer.enable_syntax();
+        buffer
+    }
 
-        let content = rope.to_string();
+    /// Creates a buffer without parsing or highlighting it, for files too large
+    /// to do so on every edit
+    pub fn create_without_syntax(name: String, rope: Rope) -> Self {
+        let id = BufferId::generate();
 
         let mut parser = Parser::new();
         parser
             .set_language(&tree_sitter_rust::language())
             .expect("Error loading Rust grammar");
 
-        let tree = parser.parse(&content, None).unwrap();
-
-        let highlight_names = [
-            "keyword", "function", "type", "number", "string", "variable",
-        ];
-        let highlighter = Highlighter::new();
-        let rust_language = tree_sitter_rust::language();
-        let mut config = HighlightConfiguration::new(
-            rust_language,
-            "rust",
-            tree_sitter_rust::HIGHLIGHTS_QUERY,
-            tree_sitter_rust::INJECTIONS_QUERY,
-            "",
-        )
-        .unwrap();
-        config.configure(&highlight_names);
-
-        let mut highlighter = HighlightCtx {
-            highlighter,
-            config,
-        };
-
-        let colors = highlighter.highlight(rope.to_string().as_bytes()).unwrap();
+        let tree = parser.parse("", None).unwrap();
 
         Self {
             id,
             name,
             view_count: 0,
+            version: 0,
+            saved_version: 0,
+            signs: Signs::default(),
+            diff_base: None,
+            diff_hunks: vec![],
+            undiffed_edit: None,
             history: History::new(),
             backing: BufferBacking::None,
+            disk_stamp: None,
+            disk_change_warned: None,
+            language: "rust".into(),
+            options: OptionOverrides::default(),
             parser,
             tree,
-            highlighter,
+            highlighter: HighlightCtx::rust(),
+            highlight_worker: None,
+            highlight_generation: 0,
+            highlight_requested: 0,
+            highlight_applied: 0,
+            visible_highlight: None,
+            unhighlighted_edit: None,
+            syntax: false,
             contents: rope,
-            colors,
+            colors: vec![],
         }
     }
 
+    /// Parses and highlights the whole buffer, and keeps doing so after edits
+    pub fn enable_syntax(&mut self) {
+        self.reparse(false);
+        self.highlighter
+            .highlight(&self.contents, &mut self.colors)
+            .unwrap();
+        self.syntax = true;
+    }
+
+    /// Parses the rope chunk by chunk, so that it never has to be copied
+    fn reparse(&mut self, incremental: bool) {
+        let contents = &self.contents;
+        let old_tree = incremental.then_some(&self.tree);
+        self.tree = self
+            .parser
+            .parse_with(
+                &mut |byte, _| {
+                    if byte >= contents.len_bytes() {
+                        return &[][..];
+                    }
+                    let (chunk, chunk_start, _, _) = contents.chunk_at_byte(byte);
+                    &chunk.as_bytes()[byte - chunk_start..]
+                },
+                old_tree,
+            )
+            .unwrap();
+    }
+
     pub fn set_backing(&mut self, backing: BufferBacking) {
         self.backing = backing;
     }
 
-    pub fn get_visible_part(&self, top_line: usize, mut line_count: usize) -> Option<RopeSlice> {
-        if self.contents.len_lines() < top_line {
-            None
-        } else {
-            line_count = line_count.min(self.contents.len_lines() - top_line);
-            let first_line = top_line;
-            let last_line = top_line + line_count - 1;
-            let first_char = self.contents.line_to_char(first_line);
-            let last_char = self.contents.line_to_char(last_line + 1);
-            Some(self.contents.slice(first_char..last_char))
+    /// Saves the buffer to its backing store.
+    ///
+    /// Unless `force` is set, this refuses to overwrite a file that has been
+    /// changed on disk since it was loaded or last saved.
+    pub fn save(&mut self, force: bool) -> anyhow::Result<()> {
+        let BufferBacking::File(path) = &self.backing else {
+            return Ok(());
+        };
+
+        if !force
+            && let Some(current) = DiskStamp::read(path)
+            && self.disk_stamp != Some(current)
+        {
+            let reason = match self.disk_stamp {
+                Some(_) => "changed on disk since it was read",
+                None => "already exists",
+            };
+            anyhow::bail!(
+                "{} {reason}; use write-force to overwrite it",
+                path.display()
+            );
         }
+
+        self.backing.save(&self.contents)?;
+        // Any recovered changes are either in what was just written or unwanted
+        let _ = std::fs::remove_file(recovery_path(path));
+        self.disk_stamp = DiskStamp::read(path);
+        self.saved_version = self.version;
+        self.reload_diff_base();
+        Ok(())
+    }
+
+    /// Whether there are edits since the buffer was last loaded or saved
+    pub fn is_modified(&self) -> bool {
+        self.version != self.saved_version
     }
 
-    pub fn insert(&mut self, view: &mut View, text: &str, char_index: usize) {
+    /// Writes the contents of a modified file-backed buffer next to its file,
+    /// for when the editor is going down without a chance to save.
+    /// Returns the path written to, if any.
+    pub fn write_recovery(&self) -> anyhow::Result<Option<std::path::PathBuf>> {
+        let BufferBacking::File(path) = &self.backing else {
+            return Ok(None);
+        };
+        if !self.is_modified() {
+            return Ok(None);
+        }
+        let recovery = recovery_path(path);
+        BufferBacking::File(recovery.clone()).save(&self.contents)?;
+        Ok(Some(recovery))
+    }
+
+    /// A recovery file left behind by a crash, unless the file has been
+    /// written since
+    pub fn recovery_file(&self) -> Option<std::path::PathBuf> {
+        let BufferBacking::File(path) = &self.backing else {
+            return None;
+        };
+        let recovery = recovery_path(path);
+        let recovered = recovery.metadata().ok()?.modified().ok()?;
+        match path.metadata().and_then(|m| m.modified()) {
+            Ok(modified) if modified > recovered => None,
+            _ => Some(recovery),
+        }
+    }
+
+    /// Text of `count` lines from `top_line`, including the newline ending the last
+    pub fn get_visible_part(&self, top_line: usize, count: usize) -> Option<RopeSlice> {
+        let total = line_count(&self.contents);
+        if top_line >= total {
+            return None;
+        }
+        let end_line = (top_line + count).min(total);
+        let first_char = self.contents.line_to_char(top_line);
+        let last_char = self.contents.line_to_char(end_line);
+        Some(self.contents.slice(first_char..last_char))
+    }
+
+    pub fn insert(&mut self, view: &mut ViewMut<impl Views>, text: &str, char_index: usize) {
         let char_index = char_index.min(self.contents.len_chars());
 
         let byte_start = self.contents.char_to_byte(char_index);
@@ -136,24 +374,25 @@ impl Buffer {
             ),
         };
 
-        self.tree.edit(&input_edit);
+        if self.syntax {
+            self.tree.edit(&input_edit);
+        }
 
         self.contents.insert(char_index, text);
-
-        let start = char_index;
-        let char_len = text.chars().count();
-
-        for selection in &mut view.selections {
-            if selection.start >= start {
-                selection.start += char_len;
-            }
-            if selection.end >= start {
-                selection.end += char_len;
-            }
-        }
+        self.version += 1;
+
+        self.edited(
+            view,
+            ViewEdit::Insert {
+                start: char_index,
+                len: text.chars().count(),
+                line: line_start,
+                lines: newline_count,
+            },
+        );
     }
 
-    pub fn remove(&mut self, view: &mut View, char_index: usize, len: usize) {
+    pub fn remove(&mut self, view: &mut ViewMut<impl Views>, char_index: usize, len: usize) {
         let char_index = char_index.min(self.contents.len_chars());
         let len = len.min(self.contents.len_chars() - char_index);
 
@@ -173,32 +412,184 @@ impl Buffer {
             new_end_position: Point::new(line_start, col_start),
         };
 
-        self.tree.edit(&input_edit);
+        if self.syntax {
+            self.tree.edit(&input_edit);
+        }
 
         self.contents.remove(char_index..char_index + len);
+        self.version += 1;
+
+        self.edited(
+            view,
+            ViewEdit::Remove {
+                start: char_index,
+                len,
+                line_start,
+                line_end,
+            },
+        );
+    }
+
+    /// Applies `edit` to the signs and to every view of this buffer
+    fn edited(&mut self, view: &mut ViewMut<impl Views>, edit: ViewEdit) {
+        match edit {
+            ViewEdit::Insert { line, lines, .. } => self.signs.insert_lines(line, lines),
+            ViewEdit::Remove {
+                line_start,
+                line_end,
+                ..
+            } => self.signs.remove_lines(line_start, line_end),
+        }
+        if self.diff_base.is_some() {
+            self.undiffed_edit = Some(Instant::now());
+        }
+        view.apply_edit_to_views(self.id, &edit);
+    }
 
-        let start = char_index;
+    pub fn recalc_tree(&mut self) {
+        if !self.syntax {
+            return;
+        }
+        self.reparse(true);
 
-        for selection in &mut view.selections {
-            if selection.start >= start {
-                selection.start = (selection.start.saturating_sub(len)).max(start);
+        // Highlighting is deferred until the next draw or a pause in editing,
+        // so that bursts of edits are only highlighted once
+        self.highlight_generation += 1;
+        self.unhighlighted_edit = Some(Instant::now());
+    }
+
+    /// Reads what the backing file looks like in git, and diffs against it
+    pub fn reload_diff_base(&mut self) {
+        self.diff_base = match &self.backing {
+            BufferBacking::File(path) => git::read_base(path),
+            _ => None,
+        };
+        self.update_diff();
+    }
+
+    /// Recomputes `diff_hunks` and the git signs
+    pub fn update_diff(&mut self) {
+        self.undiffed_edit = None;
+        self.diff_hunks = match &self.diff_base {
+            Some(base) => git::diff(base, &self.contents),
+            None => vec![],
+        };
+        self.signs.clear(SignGroup::Git);
+        for hunk in &self.diff_hunks {
+            for line in hunk.lines.clone() {
+                self.signs.set(SignGroup::Git, line, hunk.sign());
             }
-            if selection.end >= start {
-                selection.end = (selection.end.saturating_sub(len)).max(start);
+        }
+    }
+
+    /// When the git signs should be recomputed after edits
+    pub fn diff_due(&self) -> Option<Instant> {
+        self.undiffed_edit.map(|t| t + DIFF_DEBOUNCE)
+    }
+
+    /// When unhighlighted edits should be highlighted even if nothing is drawn
+    pub fn highlight_due(&self) -> Option<Instant> {
+        self.unhighlighted_edit.map(|t| t + HIGHLIGHT_DEBOUNCE)
+    }
+
+    /// Highlights edits made since the last call, or hands them to the worker
+    pub fn flush_highlight(&mut self) {
+        if self.unhighlighted_edit.take().is_none() {
+            return;
+        }
+
+        if let Some(worker) = &self.highlight_worker {
+            // The visible lines may already be highlighted for drawing
+            let skip = match &self.visible_highlight {
+                Some((generation, bytes)) if *generation == self.highlight_generation => {
+                    bytes.clone()
+                }
+                _ => 0..0,
+            };
+            let ranges = vec![0..skip.start, skip.end..self.contents.len_bytes()];
+            let request = (self.highlight_generation, self.contents.clone(), ranges);
+            if worker.requests.send(request).is_ok() {
+                self.highlight_requested = self.highlight_generation;
+                return;
             }
+            // The worker is gone; fall back to highlighting here
+            self.highlight_worker = None;
         }
+        self.highlighter
+            .highlight(&self.contents, &mut self.colors)
+            .unwrap();
+        self.highlight_requested = self.highlight_generation;
+        self.highlight_applied = self.highlight_generation;
     }
 
-    pub fn recalc_tree(&mut self) {
-        let contents = self.contents.to_string();
-        self.tree = self.parser.parse(&contents, Some(&self.tree)).unwrap();
-        self.colors = self.highlighter.highlight(contents.as_bytes()).unwrap();
+    /// Brings the colors of `lines` up to date so that they can be drawn. With
+    /// a worker, the rest of the buffer waits for a pause in editing;
+    /// without one, it is highlighted along with them.
+    pub fn highlight_visible(&mut self, lines: Range<usize>) {
+        if self.highlight_applied == self.highlight_generation {
+            return;
+        }
+        if self.highlight_worker.is_none() {
+            self.flush_highlight();
+            return;
+        }
+
+        let last_line = self.contents.len_lines();
+        let bytes = self.contents.line_to_byte(lines.start.min(last_line))
+            ..self.contents.line_to_byte(lines.end.min(last_line));
+        let visible = Some((self.highlight_generation, bytes.clone()));
+        if self.visible_highlight == visible {
+            return;
+        }
+        self.highlighter
+            .highlight_ranges(&self.contents, &mut self.colors, &[bytes])
+            .unwrap();
+        self.visible_highlight = visible;
+    }
+
+    /// Starts or stops the background highlighting worker
+    pub fn set_background_highlighting(&mut self, enabled: bool) {
+        if enabled && self.highlight_worker.is_none() {
+            self.highlight_worker = Some(HighlightWorker::spawn());
+        } else if !enabled && self.highlight_worker.take().is_some() && self.highlight_pending() {
+            self.unhighlighted_edit = Some(Instant::now());
+            self.flush_highlight();
+        }
     }
 
-    pub fn undo(&mut self, view: &mut View) {
+    /// Whether `colors` is waiting on a result from the worker
+    pub fn highlight_pending(&self) -> bool {
+        self.highlight_applied < self.highlight_requested
+    }
+
+    /// Applies the newest finished background highlight, returning whether
+    /// `colors` changed
+    pub fn poll_highlight(&mut self) -> bool {
+        let Some(worker) = &self.highlight_worker else {
+            return false;
+        };
+        let mut changed = false;
+        while let Ok((generation, ranges, colors)) = worker.results.try_recv() {
+            // Results for older text would overwrite the visible lines, which
+            // are highlighted for the current text; a newer request follows
+            if generation != self.highlight_generation {
+                continue;
+            }
+            self.colors.resize(colors.len(), Color::White);
+            for range in ranges {
+                self.colors[range.clone()].copy_from_slice(&colors[range]);
+            }
+            self.highlight_applied = generation;
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn undo(&mut self, view: &mut ViewMut<impl Views>) {
         let mut history = std::mem::take(&mut self.history);
         if let Some(action) = history.back() {
-            for action in &action.actions {
+            // Later actions were applied on top of earlier ones, so undo them first
+            for action in action.actions.iter().rev() {
                 match action {
                     Action::TextInsertion { text, start } => {
                         self.remove(view, *start, text.chars().count());
@@ -214,12 +605,11 @@ impl Buffer {
             }
             self.recalc_tree();
             view.merge_overlapping_selections();
-            view.make_selection_visisble(self);
         }
         self.history = history;
     }
 
-    pub fn redo(&mut self, view: &mut View) {
+    pub fn redo(&mut self, view: &mut ViewMut<impl Views>) {
         let mut history = std::mem::take(&mut self.history);
         if let Some(action) = history.forward() {
             for action in &action.actions {
@@ -236,7 +626,6 @@ impl Buffer {
             }
             self.recalc_tree();
             view.merge_overlapping_selections();
-            view.make_selection_visisble(self);
         }
         self.history = history;
     }
@@ -248,12 +637,17 @@ pub enum BufferBacking {
 }
 
 impl BufferBacking {
-    pub fn save(&self, buffer: &Buffer) -> anyhow::Result<()> {
+    /// Writes `contents` to the backing store.
+    ///
+    /// This only ever reads the rope, so saving can't move selections or scroll
+    /// any view. Anything that transforms the text on save must go through the
+    /// regular edit path instead.
+    pub fn save(&self, contents: &Rope) -> anyhow::Result<()> {
         match self {
             BufferBacking::None => Ok(()),
             BufferBacking::File(path) => {
                 let mut writer = std::fs::File::create(path)?;
-                for chunk in buffer.contents.chunks() {
+                for chunk in contents.chunks() {
                     writer.write_all(chunk.as_bytes())?;
                 }
 
@@ -263,18 +657,134 @@ impl BufferBacking {
     }
 }
 
+/// Whether `a` and `b` name the same file, even when spelled differently
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Where unsaved changes to `path` are written if the editor crashes
+pub fn recovery_path(path: &Path) -> std::path::PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".spiral-recover");
+    path.with_file_name(name)
+}
+
+/// Modification time and size of a file, used to notice changes made by other
+/// programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskStamp {
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+}
+
+impl DiskStamp {
+    /// Returns `None` if the file doesn't exist or can't be read
+    pub fn read(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// Highlights buffer snapshots on a background thread. The thread exits once
+/// the worker is dropped.
+pub struct HighlightWorker {
+    /// Snapshots with their generation and the byte ranges to highlight
+    requests: Sender<(u64, Rope, Vec<Range<usize>>)>,
+    /// Colors for a whole snapshot, of which only the requested ranges are set
+    results: Receiver<(u64, Vec<Range<usize>>, Vec<Color>)>,
+}
+
+impl HighlightWorker {
+    pub fn spawn() -> Self {
+        let (requests, request_rx) = mpsc::channel::<(u64, Rope, Vec<Range<usize>>)>();
+        let (result_tx, results) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut ctx = HighlightCtx::rust();
+            while let Ok(mut request) = request_rx.recv() {
+                // Only the newest snapshot matters
+                while let Ok(newer) = request_rx.try_recv() {
+                    request = newer;
+                }
+                let (generation, contents, ranges) = request;
+                let mut colors = vec![];
+                if ctx
+                    .highlight_ranges(&contents, &mut colors, &ranges)
+                    .is_err()
+                {
+                    continue;
+                }
+                if result_tx.send((generation, ranges, colors)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { requests, results }
+    }
+}
+
 pub struct HighlightCtx {
     pub highlighter: Highlighter,
     pub config: HighlightConfiguration,
+    /// Contiguous copy of the text being highlighted, kept to reuse its allocation
+    source: Vec<u8>,
 }
 
 impl HighlightCtx {
-    pub fn highlight(&mut self, text: &[u8]) -> anyhow::Result<Vec<Color>> {
+    pub fn rust() -> Self {
+        let highlight_names = [
+            "keyword", "function", "type", "number", "string", "variable",
+        ];
+        let mut config = HighlightConfiguration::new(
+            tree_sitter_rust::language(),
+            "rust",
+            tree_sitter_rust::HIGHLIGHTS_QUERY,
+            tree_sitter_rust::INJECTIONS_QUERY,
+            "",
+        )
+        .unwrap();
+        config.configure(&highlight_names);
+
+        Self {
+            highlighter: Highlighter::new(),
+            config,
+            source: vec![],
+        }
+    }
+
+    /// Fills `colors` with one color per byte of `text`
+    pub fn highlight(&mut self, text: &Rope, colors: &mut Vec<Color>) -> anyhow::Result<()> {
+        colors.clear();
+        self.highlight_ranges(text, colors, std::slice::from_ref(&(0..text.len_bytes())))
+    }
+
+    /// Resizes `colors` to one color per byte of `text`, but only sets the
+    /// colors of the bytes in `ranges`. Highlighting stops after the last range.
+    pub fn highlight_ranges(
+        &mut self,
+        text: &Rope,
+        colors: &mut Vec<Color>,
+        ranges: &[Range<usize>],
+    ) -> anyhow::Result<()> {
+        // The highlighter needs the text in one piece
+        self.source.clear();
+        for chunk in text.chunks() {
+            self.source.extend_from_slice(chunk.as_bytes());
+        }
+
         let highlights = self
             .highlighter
-            .highlight(&self.config, text, None, |_| None)?;
+            .highlight(&self.config, &self.source, None, |_| None)?;
 
-        let mut colors: Vec<Color> = vec![Color::White; text.len()];
+        colors.resize(self.source.len(), Color::White);
+        for range in ranges {
+            colors[range.clone()].fill(Color::White);
+        }
+        let last = ranges.iter().map(|r| r.end).max().unwrap_or(0);
 
         let mut color_stack: Vec<Color> = Vec::new();
 
@@ -282,12 +792,17 @@ impl HighlightCtx {
             match event? {
                 // Processed a chunk of text spanning from start..end
                 HighlightEvent::Source { start, end } => {
+                    if start >= last {
+                        break;
+                    }
                     // Sometimes you will get a source event that has no highlight,
                     // so make sure to check if there is a color on the stack
                     if let Some(color) = color_stack.last() {
-                        (start..end).for_each(|i| {
-                            colors[i] = *color;
-                        });
+                        for range in ranges {
+                            (start.max(range.start)..end.min(range.end)).for_each(|i| {
+                                colors[i] = *color;
+                            });
+                        }
                     }
                 }
                 HighlightEvent::HighlightStart(highlight) => {
@@ -309,7 +824,7 @@ impl HighlightCtx {
             }
         }
 
-        Ok(colors)
+        Ok(())
     }
 }
 
@@ -352,6 +867,24 @@ impl History {
     }
 }
 
+/// How an edit moves positions in a view, so selections and folds can follow it
+pub enum ViewEdit {
+    Insert {
+        start: usize,
+        len: usize,
+        /// Line the text was inserted on, and how many newlines it had
+        line: usize,
+        lines: usize,
+    },
+    Remove {
+        start: usize,
+        len: usize,
+        /// First and last line touched by the removed text
+        line_start: usize,
+        line_end: usize,
+    },
+}
+
 pub struct HistoryAction {
     pub actions: Vec<Action>,
 }
@@ -367,3 +900,98 @@ pub enum Action {
         len: usize,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    use super::*;
+
+    /// Char indices of every grapheme boundary in `text`, from both directions
+    fn boundaries(text: &Rope) -> (Vec<usize>, Vec<usize>) {
+        let mut forward = vec![0];
+        while *forward.last().unwrap() < text.len_chars() {
+            forward.push(next_grapheme_boundary(text, *forward.last().unwrap()));
+        }
+        let mut backward = vec![text.len_chars()];
+        while *backward.last().unwrap() > 0 {
+            backward.push(prev_grapheme_boundary(text, *backward.last().unwrap()));
+        }
+        backward.reverse();
+        (forward, backward)
+    }
+
+    #[test]
+    fn grapheme_boundaries_skip_combining_marks() {
+        // `e` followed by a combining acute accent, then two of them stacked
+        let text = Rope::from("e\u{301}x\u{301}\u{302}y");
+        assert_eq!(next_grapheme_boundary(&text, 0), 2);
+        assert_eq!(next_grapheme_boundary(&text, 2), 5);
+        assert_eq!(prev_grapheme_boundary(&text, 5), 2);
+        assert_eq!(prev_grapheme_boundary(&text, 2), 0);
+        // From inside a cluster
+        assert_eq!(next_grapheme_boundary(&text, 3), 5);
+        assert_eq!(prev_grapheme_boundary(&text, 4), 2);
+    }
+
+    #[test]
+    fn grapheme_boundaries_skip_zwj_sequences() {
+        // Woman, ZWJ, laptop: one emoji of three chars
+        let text = Rope::from("a\u{1F469}\u{200D}\u{1F4BB}b");
+        assert_eq!(next_grapheme_boundary(&text, 1), 4);
+        assert_eq!(prev_grapheme_boundary(&text, 4), 1);
+        assert_eq!(next_grapheme_boundary(&text, 4), 5);
+        assert_eq!(next_grapheme_boundary(&text, 5), 5);
+        assert_eq!(prev_grapheme_boundary(&text, 0), 0);
+    }
+
+    #[test]
+    fn grapheme_boundaries_across_chunks() {
+        // Long enough to be split into several chunks, some inside clusters
+        let source = "ab\u{1F469}\u{200D}\u{1F4BB}e\u{301}\u{302} ".repeat(500);
+        let text = Rope::from(source.as_str());
+        assert!(text.chunks().count() > 1);
+
+        let mut expected = vec![0];
+        expected.extend(
+            source
+                .grapheme_indices(true)
+                .map(|(i, g)| text.byte_to_char(i + g.len())),
+        );
+        let (forward, backward) = boundaries(&text);
+        assert_eq!(forward, expected);
+        assert_eq!(backward, expected);
+    }
+
+    #[test]
+    fn line_count_ignores_line_after_trailing_newline() {
+        assert_eq!(line_count(&Rope::from("a\nb")), 2);
+        assert_eq!(line_count(&Rope::from("a\nb\n")), 2);
+        assert_eq!(line_count(&Rope::from("")), 1);
+        assert_eq!(line_count(&Rope::from("\n")), 1);
+    }
+
+    #[test]
+    fn visible_part_ends_at_last_line() {
+        let visible = |text: &str, top_line, count| {
+            Buffer::create_without_syntax("test".into(), Rope::from(text))
+                .get_visible_part(top_line, count)
+                .map(|part| part.to_string())
+        };
+
+        assert_eq!(visible("a\nb", 0, 10).as_deref(), Some("a\nb"));
+        assert_eq!(visible("a\nb", 1, 1).as_deref(), Some("b"));
+        assert_eq!(visible("a\nb", 2, 1), None);
+
+        assert_eq!(visible("a\nb\n", 0, 10).as_deref(), Some("a\nb\n"));
+        assert_eq!(visible("a\nb\n", 0, 1).as_deref(), Some("a\n"));
+        assert_eq!(visible("a\nb\n", 1, 10).as_deref(), Some("b\n"));
+        assert_eq!(visible("a\nb\n", 2, 1), None);
+
+        assert_eq!(visible("", 0, 10).as_deref(), Some(""));
+        assert_eq!(visible("", 1, 1), None);
+
+        assert_eq!(visible("\n", 0, 10).as_deref(), Some("\n"));
+        assert_eq!(visible("\n", 1, 1), None);
+    }
+}
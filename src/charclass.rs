@@ -0,0 +1,93 @@
+use std::ops::Range;
+
+use ropey::Rope;
+
+/// Rough kinds of chars, used to find word boundaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    Newline,
+    Whitespace,
+    Word,
+    Punctuation,
+}
+
+impl CharClass {
+    /// Classifies `c`, counting letters, digits and `word_chars` as word chars
+    pub fn of(c: char, word_chars: &str) -> Self {
+        if c == '\n' {
+            CharClass::Newline
+        } else if c.is_whitespace() {
+            CharClass::Whitespace
+        } else if c.is_alphanumeric() || word_chars.contains(c) {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+/// Start of the word before `pos`, as deleted by `delete-word-backward`.
+///
+/// Whitespace before `pos` is skipped first, unless it runs back to the start
+/// of the line, in which case only the whitespace counts. A newline directly
+/// before `pos` counts as a word of its own.
+pub fn prev_word_start(text: &Rope, pos: usize, word_chars: &str) -> usize {
+    let mut i = pos.min(text.len_chars());
+    let class_before = |i: usize| CharClass::of(text.char(i - 1), word_chars);
+
+    if i == 0 || class_before(i) == CharClass::Newline {
+        return i.saturating_sub(1);
+    }
+
+    while i > 0 && class_before(i) == CharClass::Whitespace {
+        i -= 1;
+    }
+    if i < pos && (i == 0 || class_before(i) == CharClass::Newline) {
+        return i;
+    }
+
+    let class = class_before(i);
+    while i > 0 && class_before(i) == class {
+        i -= 1;
+    }
+    i
+}
+
+/// The word containing `pos`, or else the last word before it
+pub fn word_at_or_before(text: &Rope, pos: usize, word_chars: &str) -> Option<Range<usize>> {
+    let is_word = |i: usize| CharClass::of(text.char(i), word_chars) == CharClass::Word;
+
+    let mut end = (pos + 1).min(text.len_chars());
+    while end > 0 && !is_word(end - 1) {
+        end -= 1;
+    }
+    if end == 0 {
+        return None;
+    }
+    while end < text.len_chars() && is_word(end) {
+        end += 1;
+    }
+    let mut start = end - 1;
+    while start > 0 && is_word(start - 1) {
+        start -= 1;
+    }
+    Some(start..end)
+}
+
+/// The first word starting at or after `pos`
+pub fn next_word(text: &Rope, pos: usize, word_chars: &str) -> Option<Range<usize>> {
+    let is_word = |i: usize| CharClass::of(text.char(i), word_chars) == CharClass::Word;
+
+    let mut start = pos;
+    while start < text.len_chars() && !is_word(start) {
+        start += 1;
+    }
+    if start == text.len_chars() {
+        return None;
+    }
+    let mut end = start;
+    while end < text.len_chars() && is_word(end) {
+        end += 1;
+    }
+    Some(start..end)
+}
@@ -3,29 +3,46 @@ use std::{
     collections::HashMap,
     fmt::Display,
     iter::Peekable,
+    ops::Range,
+    path::PathBuf,
     rc::Rc,
     str::FromStr,
     usize,
 };
 
-use log::error;
-use mlua::IntoLua;
+use log::{debug, error, warn};
+use mlua::{FromLua, IntoLua};
 use ratatui::buffer;
+use regex::Regex;
 use ropey::Rope;
 use tree_sitter::{InputEdit, Node, Point};
 
 use crate::{
-    buffer::{Action, Buffer, BufferBacking, BufferId, HistoryAction},
-    engine::{Engine, EngineState},
+    buffer::{
+        line_count, next_grapheme_boundary, prev_grapheme_boundary, same_file, Action, Buffer,
+        BufferBacking, BufferId, DiskStamp, HistoryAction,
+    },
+    charclass::{next_word, prev_word_start, word_at_or_before},
+    diff::DiffSplit,
+    digraph,
+    engine::{parse_path_position, Engine, EngineState, PromptTarget},
     keybind::{Binding, Key},
     kill_ring::KillRingEntry,
-    selection::Selection,
-    view::{View, ViewId},
+    message::MessageLevel,
+    mode::Mode,
+    selection::{Direction, Selection},
+    view::{View, ViewId, ViewMut, Views},
 };
 
 pub struct Command {
     pub name: String,
     pub desc: String,
+    /// Names of the argument types, or `None` if the command checks its own arguments
+    pub arg_types: Option<Vec<&'static str>>,
+    /// Names of the arguments, shown next to their types in usage errors and help
+    pub arg_names: Vec<&'static str>,
+    /// Whether the command changes the buffer, which read-only views refuse
+    pub edits: bool,
     pub action: Rc<dyn Fn(Engine, Vec<CommandArg>) -> anyhow::Result<()>>,
 }
 
@@ -38,6 +55,9 @@ impl Command {
         Self {
             name: name.into(),
             desc: desc.into(),
+            arg_types: action.arg_types(),
+            arg_names: vec![],
+            edits: false,
             action: Rc::new(move |engine, args| action.apply(engine, args)),
         }
     }
@@ -50,12 +70,75 @@ impl Command {
         Self {
             name: name.into(),
             desc: desc.into(),
+            arg_types: None,
+            arg_names: vec![],
+            edits: false,
             action: Rc::new(move |engine, args| {
                 action.call::<_, ()>(args)?;
                 Ok(())
             }),
         }
     }
+
+    /// Marks the command as changing the buffer
+    pub fn edits(mut self) -> Self {
+        self.edits = true;
+        self
+    }
+
+    /// Names the arguments, in order
+    pub fn arg_names(mut self, names: &[&'static str]) -> Self {
+        self.arg_names = names.to_vec();
+        self
+    }
+
+    /// The arguments taken, e.g. `<line: integer> <string>`, if they are known
+    pub fn signature(&self) -> Option<String> {
+        Some(
+            self.params()?
+                .map(|(name, ty)| match name {
+                    Some(name) => format!("<{name}: {ty}>"),
+                    None => format!("<{ty}>"),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+
+    /// Describes the expected arguments, if they are known
+    pub fn usage(&self) -> Option<String> {
+        let name = &self.name;
+        let params = self
+            .params()?
+            .map(|(name, ty)| match name {
+                Some(name) => format!("{name} ({ty})"),
+                None => ty.to_string(),
+            })
+            .collect::<Vec<_>>();
+        Some(match params.len() {
+            0 => format!("{name} takes no arguments"),
+            1 => format!("{name} expects 1 argument: {}", params[0]),
+            n => format!("{name} expects {n} arguments: {}", params.join(", ")),
+        })
+    }
+
+    /// Checks the number of arguments against the argument types, if they are known
+    pub fn check_arg_count(&self, args: &[CommandArg]) -> anyhow::Result<()> {
+        match (&self.arg_types, self.usage()) {
+            (Some(types), Some(usage)) if types.len() != args.len() => anyhow::bail!("{usage}"),
+            _ => Ok(()),
+        }
+    }
+
+    fn params(&self) -> Option<impl Iterator<Item = (Option<&'static str>, &'static str)> + '_> {
+        let types = self.arg_types.as_ref()?;
+        Some(
+            types
+                .iter()
+                .enumerate()
+                .map(|(i, ty)| (self.arg_names.get(i).copied(), *ty)),
+        )
+    }
 }
 
 fn views_buffers<'a>(
@@ -67,21 +150,25 @@ fn views_buffers<'a>(
     RefMut::map_split(state, |s| (&mut s.views, &mut s.buffers))
 }
 
-fn view_buffer<'a>(state: RefMut<EngineState>) -> (RefMut<View>, RefMut<Buffer>) {
+/// The active view, borrowed along with the others so that edits move them too
+type ActiveView<'a> = ViewMut<RefMut<'a, HashMap<ViewId, View>>>;
+
+fn view_buffer<'a>(state: RefMut<EngineState>) -> (ActiveView, RefMut<Buffer>) {
     let view_id = state.active_view;
-    let (mut views, buffers) = views_buffers(state);
-    let view = RefMut::map(views, |v| v.get_mut(&view_id).unwrap());
+    let (views, buffers) = views_buffers(state);
+    let view = ViewMut::new(views, view_id);
     let buffer = RefMut::map(buffers, |b| b.get_mut(&view.buffer).unwrap());
     (view, buffer)
 }
 
 fn for_selection_mut(engine: Engine, mut f: impl FnMut(&mut Selection, &mut Buffer)) {
     let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
     let (mut view, mut buffer) = view_buffer(state);
     for selection in &mut view.selections {
         f(selection, &mut buffer);
     }
-    view.make_selection_visisble(&buffer);
+    view.make_selection_visisble(&buffer, scrolloff);
 }
 
 fn get_head_pos(selection: &Selection, buffer: &Buffer) -> (usize, usize) {
@@ -91,8 +178,9 @@ fn get_head_pos(selection: &Selection, buffer: &Buffer) -> (usize, usize) {
     (line, col)
 }
 
-fn set_head_pos(selection: &mut Selection, buffer: &Buffer, line: usize, col: usize) {
-    let line = line.min(buffer.contents.len_lines());
+/// Moves the head of `selection` to a 0-based line and column, clamped to the buffer
+pub fn set_head_pos(selection: &mut Selection, buffer: &Buffer, line: usize, col: usize) {
+    let line = line.min(buffer.contents.len_lines() - 1);
     let max_col = if line == buffer.contents.len_lines() - 1 {
         buffer.contents.line(line).len_chars()
     } else {
@@ -103,7 +191,14 @@ fn set_head_pos(selection: &mut Selection, buffer: &Buffer, line: usize, col: us
     selection.make_valid(&buffer.contents);
 }
 
-fn collapse_cursor(selection: &mut Selection) {
+/// Whether `selection` covers whole lines, up to and including a newline
+fn is_linewise(selection: &Selection, text: &Rope) -> bool {
+    selection.end < text.len_chars()
+        && text.char(selection.end) == '\n'
+        && selection.start == text.line_to_char(text.char_to_line(selection.start))
+}
+
+pub fn collapse_cursor(selection: &mut Selection) {
     let (head, anchor) = selection.head_anchor_mut();
     *anchor = *head;
 }
@@ -112,78 +207,107 @@ fn collapse_cursor(selection: &mut Selection) {
 
 fn move_char_right(engine: Engine) {
     for_selection_mut(engine, |sel, buf| {
-        let (head, anchor) = sel.head_anchor_mut();
-        *head += 1;
+        let head = sel.head_mut();
+        *head = next_grapheme_boundary(&buf.contents, *head);
         sel.make_valid(&buf.contents);
     });
 }
 
 fn move_char_left(engine: Engine) {
     for_selection_mut(engine, |sel, buf| {
-        let (head, anchor) = sel.head_anchor_mut();
-        *head = head.saturating_sub(1);
+        let head = sel.head_mut();
+        *head = prev_grapheme_boundary(&buf.contents, *head);
         sel.make_valid(&buf.contents);
     });
 }
 
 fn move_char_up(engine: Engine) {
-    for_selection_mut(engine, |sel, buf| {
-        let (line, col) = get_head_pos(sel, buf);
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let (mut view, buf) = view_buffer(state);
+    let view = &mut *view;
+    for sel in &mut view.selections {
+        let (line, col) = get_head_pos(sel, &buf);
         if line == 0 {
             *sel.head_mut() = 0;
             sel.make_valid(&buf.contents);
         } else {
-            set_head_pos(sel, buf, line.saturating_sub(1), col);
+            set_head_pos(sel, &buf, view.folds.prev_visible(line), col);
         }
-    });
+    }
+    view.make_selection_visisble(&buf, scrolloff);
 }
 
 fn move_char_down(engine: Engine) {
-    for_selection_mut(engine, |sel, buf| {
-        let (line, col) = get_head_pos(sel, buf);
-        if line + 1 >= buf.contents.len_lines() {
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let (mut view, buf) = view_buffer(state);
+    let view = &mut *view;
+    for sel in &mut view.selections {
+        let (line, col) = get_head_pos(sel, &buf);
+        let next = view.folds.next_visible(line);
+        if next >= buf.contents.len_lines() {
             *sel.head_mut() = usize::MAX;
             sel.make_valid(&buf.contents);
         } else {
-            set_head_pos(sel, buf, line + 1, col);
+            set_head_pos(sel, &buf, next, col);
         }
-    });
+    }
+    view.make_selection_visisble(&buf, scrolloff);
 }
 
 fn delete(engine: Engine) {
     let mut state = engine.state_mut();
     let state = &mut *state;
-    let view = state.views.get_mut(&state.active_view).unwrap();
+    let scrolloff = state.active_options().scrolloff;
+    let mut view = ViewMut::new(&mut state.views, state.active_view);
     let buffer = state.buffers.get_mut(&view.buffer).unwrap();
 
     let mut texts = vec![];
     let mut actions = vec![];
+    let linewise = view
+        .selections
+        .iter()
+        .all(|s| is_linewise(s, &buffer.contents));
 
     for i in 0..view.selections.len() {
         let s = view.selections[i];
+        let end = (s.end + 1).min(buffer.contents.len_chars());
+        if end <= s.start {
+            continue;
+        }
 
-        let text = buffer.contents.slice(s.start..=s.end).to_string();
+        let text = buffer.contents.slice(s.start..end).to_string();
         texts.push(text.clone());
 
-        buffer.remove(view, s.start, s.end - s.start + 1);
+        buffer.remove(&mut view, s.start, end - s.start);
         actions.push(Action::TextDeletion {
             deleted_text: text,
             start: s.start,
-            len: s.end - s.start + 1,
+            len: end - s.start,
         });
     }
 
     buffer.history.register_edit(HistoryAction { actions });
     buffer.recalc_tree();
 
-    state.kill_ring.add_entry(KillRingEntry::new(texts));
+    state.kill_ring.add_entry(
+        KillRingEntry::new(texts).linewise(linewise),
+        state.editor_options.kill_ring_max,
+    );
 
     view.merge_overlapping_selections();
-    view.make_selection_visisble(buffer);
+    view.make_selection_visisble(buffer, scrolloff);
 }
 
 fn backspace(engine: Engine) {
     let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let pairs = if state.active_options().auto_pairs {
+        state.pairs.clone()
+    } else {
+        vec![]
+    };
     let (mut view, mut buffer) = view_buffer(state);
 
     let mut actions = vec![];
@@ -194,60 +318,1342 @@ fn backspace(engine: Engine) {
             continue;
         }
 
-        let text = buffer.contents.slice(s.start - 1..s.start).to_string();
-        buffer.remove(&mut view, s.start - 1, 1);
+        // Deleting the opening char of an empty pair deletes the closing one too
+        let prev = buffer.contents.char(s.start - 1);
+        let next = buffer.contents.get_char(s.start);
+        let len = match next {
+            Some(next) if pairs.contains(&(prev, next)) => 2,
+            _ => 1,
+        };
+
+        let text = buffer
+            .contents
+            .slice(s.start - 1..s.start - 1 + len)
+            .to_string();
+        buffer.remove(&mut view, s.start - 1, len);
 
         actions.push(Action::TextDeletion {
             deleted_text: text,
             start: s.start - 1,
+            len,
+        });
+    }
+
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(&buffer, scrolloff);
+}
+
+/// Deletes the word before each selection, like Ctrl-W in a shell
+fn delete_word_backward(engine: Engine) {
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let word_chars = state.active_options().word_chars;
+    let (mut view, mut buffer) = view_buffer(state);
+
+    let mut actions = vec![];
+
+    for i in 0..view.selections.len() {
+        let s = view.selections[i];
+        // Don't eat into the selection before this one
+        let limit = match i {
+            0 => 0,
+            _ => view.selections[i - 1].end + 1,
+        };
+        let start = prev_word_start(&buffer.contents, s.start, &word_chars).max(limit);
+        if start >= s.start {
+            continue;
+        }
+
+        let len = s.start - start;
+        let text = buffer.contents.slice(start..s.start).to_string();
+        buffer.remove(&mut view, start, len);
+
+        actions.push(Action::TextDeletion {
+            deleted_text: text,
+            start,
+            len,
+        });
+    }
+
+    if !actions.is_empty() {
+        buffer.history.register_edit(HistoryAction { actions });
+        buffer.recalc_tree();
+    }
+
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(&buffer, scrolloff);
+}
+
+/// Replaces `len` chars at `start` with `text`, recording both halves of the edit
+fn replace_range(
+    buffer: &mut Buffer,
+    view: &mut ViewMut<impl Views>,
+    start: usize,
+    len: usize,
+    text: String,
+    actions: &mut Vec<Action>,
+) {
+    let deleted_text = buffer.contents.slice(start..start + len).to_string();
+    buffer.remove(view, start, len);
+    actions.push(Action::TextDeletion {
+        deleted_text,
+        start,
+        len,
+    });
+    buffer.insert(view, &text, start);
+    actions.push(Action::TextInsertion { text, start });
+}
+
+/// Swaps the char before each cursor with the one under it and moves forward,
+/// or swaps the two chars before the cursor at the end of a line
+fn transpose_chars(engine: Engine) {
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let (mut view, mut buffer) = view_buffer(state);
+
+    let mut actions = vec![];
+
+    for i in 0..view.selections.len() {
+        let head = view.selections[i].head();
+        let text = &buffer.contents;
+        let at_line_end = head >= text.len_chars() || text.char(head) == '\n';
+        let Some(start) = head.checked_sub(if at_line_end { 2 } else { 1 }) else {
+            continue;
+        };
+        let (a, b) = (text.char(start), text.char(start + 1));
+        if a == '\n' || b == '\n' {
+            continue;
+        }
+        replace_range(
+            &mut buffer,
+            &mut view,
+            start,
+            2,
+            format!("{b}{a}"),
+            &mut actions,
+        );
+    }
+
+    if !actions.is_empty() {
+        buffer.history.register_edit(HistoryAction { actions });
+        buffer.recalc_tree();
+    }
+
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(&buffer, scrolloff);
+}
+
+/// Swaps the word under or before each cursor with the next word, leaving the
+/// cursor after them
+fn transpose_words(engine: Engine) {
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let word_chars = state.active_options().word_chars;
+    let (mut view, mut buffer) = view_buffer(state);
+
+    let mut actions = vec![];
+
+    for i in 0..view.selections.len() {
+        let head = view.selections[i].head();
+        let text = &buffer.contents;
+        let Some(first) = word_at_or_before(text, head, &word_chars) else {
+            continue;
+        };
+        let Some(second) = next_word(text, first.end, &word_chars) else {
+            continue;
+        };
+        let swapped = format!(
+            "{}{}{}",
+            text.slice(second.clone()),
+            text.slice(first.end..second.start),
+            text.slice(first.clone()),
+        );
+        replace_range(
+            &mut buffer,
+            &mut view,
+            first.start,
+            second.end - first.start,
+            swapped,
+            &mut actions,
+        );
+    }
+
+    if !actions.is_empty() {
+        buffer.history.register_edit(HistoryAction { actions });
+        buffer.recalc_tree();
+    }
+
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(&buffer, scrolloff);
+}
+
+fn insert(engine: Engine, text: String) {
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let (mut view, mut buffer) = view_buffer(state);
+
+    let mut actions = vec![];
+
+    for i in 0..view.selections.len() {
+        let s = view.selections[i];
+        buffer.insert(&mut view, &text, s.start);
+        let action = Action::TextInsertion {
+            text: text.clone(),
+            start: s.start,
+        };
+        actions.push(action);
+    }
+
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+
+    view.make_selection_visisble(&buffer, scrolloff);
+}
+
+/// Collapses each selection onto its start, or onto the char after its end,
+/// and enters insert mode
+fn enter_insert(engine: Engine, append: bool) {
+    for_selection_mut(engine.clone(), |sel, buf| {
+        let pos = if append { sel.end + 1 } else { sel.start };
+        sel.start = pos;
+        sel.end = pos;
+        sel.make_valid(&buf.contents);
+    });
+    engine.state_mut().current_mode = Mode::Insert;
+}
+
+/// Puts a cursor on every line of each selection, at the leftmost column of the
+/// selection's ends, and enters insert mode. Lines too short to reach that
+/// column are skipped.
+fn block_insert(engine: Engine) {
+    let state = engine.state_mut();
+    let (mut view, buffer) = view_buffer(state);
+    let text = &buffer.contents;
+
+    let mut selections = vec![];
+    let mut primary_index = 0;
+    for (i, s) in view.selections.iter().enumerate() {
+        let start = s.start.min(text.len_chars());
+        let end = s.end.min(text.len_chars());
+        let (start_line, end_line) = (text.char_to_line(start), text.char_to_line(end));
+        let col = (start - text.line_to_char(start_line)).min(end - text.line_to_char(end_line));
+
+        if i == view.primary_index {
+            primary_index = selections.len();
+        }
+        for line in start_line..=end_line {
+            let len = text.line(line).chars().take_while(|c| *c != '\n').count();
+            if len < col {
+                continue;
+            }
+            let pos = text.line_to_char(line) + col;
+            selections.push(Selection {
+                view: view.id,
+                start: pos,
+                end: pos,
+                dir: Direction::Forward,
+            });
+        }
+    }
+
+    view.selections = selections;
+    view.primary_index = primary_index;
+    view.clamp_primary();
+    view.merge_overlapping_selections();
+    drop((view, buffer));
+    engine.state_mut().current_mode = Mode::Insert;
+}
+
+/// Opens a new line below or above each selection, keeping the indentation
+/// of the selection's line, and enters insert mode
+fn open_line(engine: Engine, below: bool) {
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let (mut view, mut buffer) = view_buffer(state);
+
+    let mut actions = vec![];
+
+    for i in 0..view.selections.len() {
+        let s = view.selections[i];
+        let text = &buffer.contents;
+        let line = text.char_to_line(if below { s.end } else { s.start }.min(text.len_chars()));
+        let indent = text
+            .line(line)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect::<String>();
+
+        let (pos, inserted, cursor) = if below {
+            let pos = if line + 1 < text.len_lines() {
+                text.line_to_char(line + 1) - 1
+            } else {
+                text.len_chars()
+            };
+            (pos, format!("\n{indent}"), pos + 1 + indent.chars().count())
+        } else {
+            let pos = text.line_to_char(line);
+            (pos, format!("{indent}\n"), pos + indent.chars().count())
+        };
+
+        buffer.insert(&mut view, &inserted, pos);
+        actions.push(Action::TextInsertion {
+            text: inserted,
+            start: pos,
+        });
+        let sel = &mut view.selections[i];
+        sel.start = cursor;
+        sel.end = cursor;
+    }
+
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(&buffer, scrolloff);
+    drop((view, buffer));
+
+    engine.state_mut().current_mode = Mode::Insert;
+}
+
+/// The chars replaced by one typed char in overwrite mode, so that backspace
+/// can put them back
+pub struct Overwritten {
+    pub view: ViewId,
+    /// Each cursor after the typed char, and the char it replaced if it
+    /// wasn't added at the end of a line
+    pub chars: Vec<(usize, Option<char>)>,
+}
+
+/// Replaces the char at each selection with a typed char, inserting instead at
+/// the end of a line
+fn overwrite_char(engine: Engine, c: char) {
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let active_view = state.active_view;
+    let (mut view, mut buffer) = view_buffer(state);
+
+    let mut actions = vec![];
+    let mut replaced = vec![];
+
+    for i in 0..view.selections.len() {
+        let s = view.selections[i];
+        let next = buffer
+            .contents
+            .get_char(s.start)
+            .filter(|&next| next != '\n' && next != '\r');
+        if let Some(next) = next {
+            buffer.remove(&mut view, s.start, 1);
+            actions.push(Action::TextDeletion {
+                deleted_text: next.to_string(),
+                start: s.start,
+                len: 1,
+            });
+        }
+        replaced.push(next);
+        buffer.insert(&mut view, &c.to_string(), s.start);
+        actions.push(Action::TextInsertion {
+            text: c.to_string(),
+            start: s.start,
+        });
+    }
+
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+
+    view.make_selection_visisble(&buffer, scrolloff);
+    let chars = view
+        .selections
+        .iter()
+        .map(|s| s.start)
+        .zip(replaced)
+        .collect();
+    drop((view, buffer));
+
+    engine.state_mut().overwritten.push(Overwritten {
+        view: active_view,
+        chars,
+    });
+}
+
+/// Moves each cursor left in overwrite mode. Right after typing, this puts
+/// back the chars that were replaced, and removes the ones that were added.
+fn overwrite_backspace(engine: Engine) {
+    let mut state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let active_view = state.active_view;
+    let last = state.overwritten.pop();
+    let (mut view, mut buffer) = view_buffer(state);
+
+    // Anything done since typing, like moving the cursors, leaves nothing to
+    // restore
+    let last = last.filter(|last| {
+        last.view == active_view
+            && last
+                .chars
+                .iter()
+                .map(|&(pos, _)| pos)
+                .eq(view.selections.iter().map(|s| s.start))
+    });
+    let Some(last) = last else {
+        drop((view, buffer));
+        engine.state_mut().overwritten.clear();
+        for_selection_mut(engine, |sel, _| {
+            sel.start = sel.start.saturating_sub(1);
+            sel.end = sel.start;
+        });
+        return;
+    };
+
+    let mut actions = vec![];
+
+    for (i, (_, replaced)) in last.chars.into_iter().enumerate() {
+        let pos = view.selections[i].start - 1;
+        let typed = buffer.contents.char(pos).to_string();
+        buffer.remove(&mut view, pos, 1);
+        actions.push(Action::TextDeletion {
+            deleted_text: typed,
+            start: pos,
+            len: 1,
+        });
+        if let Some(replaced) = replaced {
+            buffer.insert(&mut view, &replaced.to_string(), pos);
+            actions.push(Action::TextInsertion {
+                text: replaced.to_string(),
+                start: pos,
+            });
+        }
+        let sel = &mut view.selections[i];
+        sel.start = pos;
+        sel.end = pos;
+    }
+
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+
+    view.make_selection_visisble(&buffer, scrolloff);
+}
+
+/// Lines touched by any selection, last line first
+fn selected_lines(view: &View, text: &Rope) -> Vec<usize> {
+    let mut lines = view
+        .selections
+        .iter()
+        .flat_map(|s| {
+            let end = s.end.min(text.len_chars());
+            text.char_to_line(s.start)..=text.char_to_line(end)
+        })
+        .collect::<Vec<_>>();
+    lines.sort_unstable();
+    lines.dedup();
+    lines.reverse();
+    lines
+}
+
+/// Adds one level of indentation to every non-blank selected line
+fn indent(engine: Engine) {
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let unit = state.active_options().indent_unit();
+    let mut view = ViewMut::new(&mut state.views, state.active_view);
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    let mut actions = vec![];
+
+    for line in selected_lines(&view, &buffer.contents) {
+        if buffer.contents.line(line).chars().all(char::is_whitespace) {
+            continue;
+        }
+        let start = buffer.contents.line_to_char(line);
+        buffer.insert(&mut view, &unit, start);
+        actions.push(Action::TextInsertion {
+            text: unit.clone(),
+            start,
+        });
+    }
+
+    if actions.is_empty() {
+        return;
+    }
+
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+    view.make_selection_visisble(buffer, scrolloff);
+}
+
+/// Removes one level of indentation from every selected line
+fn dedent(engine: Engine) {
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let width = state.active_options().indent_width.get();
+    let mut view = ViewMut::new(&mut state.views, state.active_view);
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    let mut actions = vec![];
+
+    for line in selected_lines(&view, &buffer.contents) {
+        let start = buffer.contents.line_to_char(line);
+        let mut chars = buffer.contents.line(line).chars();
+        let len = match chars.next() {
+            Some('\t') => 1,
+            Some(' ') => 1 + chars.take(width - 1).take_while(|c| *c == ' ').count(),
+            _ => continue,
+        };
+        let deleted_text = buffer.contents.slice(start..start + len).to_string();
+        buffer.remove(&mut view, start, len);
+        actions.push(Action::TextDeletion {
+            deleted_text,
+            start,
+            len,
+        });
+    }
+
+    if actions.is_empty() {
+        return;
+    }
+
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+    view.make_selection_visisble(buffer, scrolloff);
+}
+
+/// Index of the last char of the line, i.e. its newline if it has one
+fn line_end_char(text: &Rope, line: usize) -> usize {
+    if line + 1 < text.len_lines() {
+        text.line_to_char(line + 1) - 1
+    } else {
+        text.len_chars().saturating_sub(1)
+    }
+    .max(text.line_to_char(line))
+}
+
+/// Extends each selection to cover its lines, including the trailing newline.
+/// Selections already covering whole lines are extended to the next line.
+fn select_line(engine: Engine) {
+    for_selection_mut(engine, |sel, buf| {
+        let text = &buf.contents;
+        let line_end = |line: usize| line_end_char(text, line);
+
+        let start = text.line_to_char(text.char_to_line(sel.start));
+        let mut end_line = text.char_to_line(sel.end);
+        if sel.start == start
+            && sel.end == line_end(end_line)
+            && end_line + 1 < text.len_lines()
+            && text.line_to_char(end_line + 1) < text.len_chars()
+        {
+            end_line += 1;
+        }
+
+        sel.start = start;
+        sel.end = line_end(end_line);
+        sel.dir = Direction::Forward;
+    });
+}
+
+/// Replaces the selections with one covering the lines `first..=last`,
+/// counted from 1 and clamped to the buffer
+fn select_lines(engine: Engine, first: i32, last: i32) {
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let (mut view, buffer) = view_buffer(state);
+    let text = &buffer.contents;
+    // A trailing newline doesn't start another line to select
+    let last_line = line_count(text) - 1;
+    let line = |n: i32| (n.max(1) as usize - 1).min(last_line);
+    let (first, last) = (line(first.min(last)), line(first.max(last)));
+
+    let id = view.id;
+    view.selections = vec![Selection {
+        view: id,
+        start: text.line_to_char(first),
+        end: line_end_char(text, last),
+        dir: Direction::Forward,
+    }];
+    view.primary_index = 0;
+    view.make_selection_visisble(&buffer, scrolloff);
+}
+
+/// Finds the nearest `open`/`close` pair enclosing `start..=end`, returning their char indices
+fn find_enclosing_pair(
+    text: &Rope,
+    start: usize,
+    end: usize,
+    open: char,
+    close: char,
+) -> Option<(usize, usize)> {
+    let len = text.len_chars();
+    if len == 0 {
+        return None;
+    }
+    let (start, end) = (start.min(len - 1), end.min(len - 1));
+
+    if open == close {
+        // Quotes don't nest, but can be escaped
+        let is_quote = |i: usize| text.char(i) == open && (i == 0 || text.char(i - 1) != '\\');
+        let before = (0..=start).rev().find(|i| is_quote(*i))?;
+        let after = (end.max(before + 1)..len).find(|i| is_quote(*i))?;
+        return Some((before, after));
+    }
+
+    let mut depth = 0;
+    let mut before = None;
+    for i in (0..=start).rev() {
+        match text.char(i) {
+            c if c == close && i != start => depth += 1,
+            c if c == open && depth == 0 => {
+                before = Some(i);
+                break;
+            }
+            c if c == open => depth -= 1,
+            _ => {}
+        }
+    }
+
+    let mut depth = 0;
+    let mut after = None;
+    for i in end.max(before? + 1)..len {
+        match text.char(i) {
+            c if c == open && i != end => depth += 1,
+            c if c == close && depth == 0 => {
+                after = Some(i);
+                break;
+            }
+            c if c == close => depth -= 1,
+            _ => {}
+        }
+    }
+
+    Some((before?, after?))
+}
+
+/// Like [`find_enclosing_pair`], but using the smallest syntax node delimited
+/// by the pair, which keeps brackets in strings and comments from matching
+fn find_enclosing_pair_node(
+    buffer: &Buffer,
+    start: usize,
+    end: usize,
+    open: char,
+    close: char,
+) -> Option<(usize, usize)> {
+    if !buffer.syntax {
+        return None;
+    }
+    let text = &buffer.contents;
+    let len = text.len_chars();
+    let start_byte = text.char_to_byte(start.min(len));
+    let end_byte = text.char_to_byte((end + 1).min(len));
+    let mut node = buffer
+        .tree
+        .root_node()
+        .descendant_for_byte_range(start_byte, end_byte);
+
+    // The delimiters must be tokens of the node itself, so that e.g. `(a) + (b)`
+    // isn't taken for a pair
+    let is_token = |node: Option<tree_sitter::Node>, c: char| {
+        node.is_some_and(|node| {
+            let range = node.byte_range();
+            range.len() == c.len_utf8() && text.char(text.byte_to_char(range.start)) == c
+        })
+    };
+
+    while let Some(n) = node {
+        let range = n.byte_range();
+        let first = text.byte_to_char(range.start);
+        let last = text.byte_to_char(range.end).saturating_sub(1);
+        if first < last
+            && first <= start
+            && last >= end
+            && is_token(n.child(0), open)
+            && is_token(n.child(n.child_count().saturating_sub(1)), close)
+        {
+            return Some((first, last));
+        }
+        node = n.parent();
+    }
+    None
+}
+
+/// Selects the contents of the nearest pair around each selection, along with
+/// the pair itself if `around` is set
+fn select_pair(engine: Engine, c: char, around: bool) -> anyhow::Result<()> {
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let (open, close) = state.pair_for(c);
+    let view = state.views.get_mut(&state.active_view).unwrap();
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    let mut found = false;
+    for sel in &mut view.selections {
+        let Some((before, after)) =
+            find_enclosing_pair_node(buffer, sel.start, sel.end, open, close)
+                .or_else(|| find_enclosing_pair(&buffer.contents, sel.start, sel.end, open, close))
+        else {
+            continue;
+        };
+        found = true;
+
+        if around {
+            sel.start = before;
+            sel.end = after;
+        } else if before + 1 < after {
+            sel.start = before + 1;
+            sel.end = after - 1;
+        } else {
+            // Nothing inside; put the cursor on the closing char
+            sel.start = after;
+            sel.end = after;
+        }
+    }
+
+    if !found {
+        anyhow::bail!("No surrounding {open}{close} found");
+    }
+
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(buffer, scrolloff);
+    Ok(())
+}
+
+fn surround(engine: Engine, pair: String) -> anyhow::Result<()> {
+    let Some(c) = pair.chars().next() else {
+        anyhow::bail!("surround expects a pair character");
+    };
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let (open, close) = state.pair_for(c);
+    let mut view = ViewMut::new(&mut state.views, state.active_view);
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    let mut actions = vec![];
+
+    for i in 0..view.selections.len() {
+        let s = view.selections[i];
+        let after = (s.end + 1).min(buffer.contents.len_chars());
+
+        buffer.insert(&mut view, &close.to_string(), after);
+        actions.push(Action::TextInsertion {
+            text: close.to_string(),
+            start: after,
+        });
+        buffer.insert(&mut view, &open.to_string(), s.start);
+        actions.push(Action::TextInsertion {
+            text: open.to_string(),
+            start: s.start,
+        });
+
+        let sel = &mut view.selections[i];
+        sel.start = s.start;
+        sel.end = after + 1;
+        sel.make_valid(&buffer.contents);
+    }
+
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+
+    view.make_selection_visisble(buffer, scrolloff);
+    Ok(())
+}
+
+fn delete_surround(engine: Engine, pair: String) -> anyhow::Result<()> {
+    let Some(c) = pair.chars().next() else {
+        anyhow::bail!("delete-surround expects a pair character");
+    };
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let (open, close) = state.pair_for(c);
+    let mut view = ViewMut::new(&mut state.views, state.active_view);
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    let mut actions = vec![];
+
+    for i in 0..view.selections.len() {
+        let s = view.selections[i];
+        let Some((before, after)) =
+            find_enclosing_pair(&buffer.contents, s.start, s.end, open, close)
+        else {
+            continue;
+        };
+
+        buffer.remove(&mut view, after, 1);
+        actions.push(Action::TextDeletion {
+            deleted_text: close.to_string(),
+            start: after,
             len: 1,
         });
+        buffer.remove(&mut view, before, 1);
+        actions.push(Action::TextDeletion {
+            deleted_text: open.to_string(),
+            start: before,
+            len: 1,
+        });
+    }
+
+    if actions.is_empty() {
+        anyhow::bail!("No surrounding {open}{close} found");
     }
 
     buffer.history.register_edit(HistoryAction { actions });
     buffer.recalc_tree();
 
-    view.merge_overlapping_selections();
-    view.make_selection_visisble(&buffer);
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(buffer, scrolloff);
+    Ok(())
+}
+
+/// Inserts a typed char before each selection, handling auto-pairs
+pub fn insert_char(engine: Engine, c: char) {
+    if engine.state().overwrite {
+        overwrite_char(engine, c);
+        return;
+    }
+
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let pair = state
+        .pairs
+        .iter()
+        .copied()
+        .find(|(open, close)| *open == c || *close == c)
+        .filter(|_| state.active_options().auto_pairs);
+    let mut view = ViewMut::new(&mut state.views, state.active_view);
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    let mut actions = vec![];
+
+    for i in 0..view.selections.len() {
+        let s = view.selections[i];
+        let next = buffer.contents.get_char(s.start);
+        let prev = s
+            .start
+            .checked_sub(1)
+            .and_then(|i| buffer.contents.get_char(i));
+
+        match pair {
+            // Type over an existing closing char
+            Some((_, close)) if c == close && next == Some(close) => {
+                let sel = &mut view.selections[i];
+                sel.start += 1;
+                sel.end += 1;
+                sel.make_valid(&buffer.contents);
+            }
+            // Quotes directly after a word are most likely apostrophes
+            Some((open, close))
+                if c == open && !(open == close && prev.is_some_and(char::is_alphanumeric)) =>
+            {
+                let text = format!("{open}{close}");
+                buffer.insert(&mut view, &text, s.start);
+                actions.push(Action::TextInsertion {
+                    text,
+                    start: s.start,
+                });
+                let sel = &mut view.selections[i];
+                sel.start -= 1;
+                sel.end -= 1;
+            }
+            _ => {
+                buffer.insert(&mut view, &c.to_string(), s.start);
+                actions.push(Action::TextInsertion {
+                    text: c.to_string(),
+                    start: s.start,
+                });
+            }
+        }
+    }
+
+    if !actions.is_empty() {
+        buffer.history.register_edit(HistoryAction { actions });
+        buffer.recalc_tree();
+    }
+
+    view.make_selection_visisble(buffer, scrolloff);
+}
+
+/// Returns the char ranges of all matches of `pattern` in the buffer
+pub fn search_matches(buffer: &Buffer, pattern: &str) -> anyhow::Result<Vec<Range<usize>>> {
+    let regex = Regex::new(pattern)?;
+    let text = buffer.contents.to_string();
+    Ok(regex
+        .find_iter(&text)
+        .map(|m| buffer.contents.byte_to_char(m.start())..buffer.contents.byte_to_char(m.end()))
+        .collect())
+}
+
+/// Where the primary selection is among the matches of the last search
+#[derive(Clone, Copy)]
+pub struct SearchCount {
+    pub view: ViewId,
+    /// Buffer version the matches were counted in
+    pub version: u64,
+    /// Start and end of the primary selection when counted
+    pub selection: (usize, usize),
+    /// 1-based index of the match the primary selection is on
+    pub index: usize,
+    pub total: usize,
+}
+
+impl SearchCount {
+    /// Counts the matches of the last search in the active view, if its
+    /// primary selection is on one of them
+    pub fn count(state: &EngineState) -> Option<Self> {
+        let pattern = state.last_search.as_ref()?;
+        let view = state.views.get(&state.active_view)?;
+        let buffer = state.buffers.get(&view.buffer)?;
+        let primary = view.primary_selection()?;
+        let matches = search_matches(buffer, pattern).ok()?;
+        let index = matches.iter().position(|m| {
+            m.start == primary.start && m.end.saturating_sub(1).max(m.start) == primary.end
+        })?;
+        Some(Self {
+            view: view.id,
+            version: buffer.version,
+            selection: (primary.start, primary.end),
+            index: index + 1,
+            total: matches.len(),
+        })
+    }
+
+    /// Whether the count still holds for the active view
+    pub fn is_current(&self, state: &EngineState) -> bool {
+        let Some(view) = state.views.get(&state.active_view) else {
+            return false;
+        };
+        view.id == self.view
+            && state.buffers[&view.buffer].version == self.version
+            && view
+                .primary_selection()
+                .is_some_and(|sel| (sel.start, sel.end) == self.selection)
+    }
+}
+
+/// Moves each selection to its next (or previous) match of the last search, wrapping around
+fn search_next(engine: Engine, forward: bool) -> anyhow::Result<()> {
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let Some(pattern) = &state.last_search else {
+        anyhow::bail!("No previous search");
+    };
+    let view = state.views.get_mut(&state.active_view).unwrap();
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    let matches = search_matches(buffer, pattern)?;
+    let (Some(first), Some(last)) = (matches.first(), matches.last()) else {
+        anyhow::bail!("No matches for {pattern}");
+    };
+
+    for sel in &mut view.selections {
+        let m = if forward {
+            matches
+                .iter()
+                .find(|m| m.start > sel.start)
+                .unwrap_or(first)
+        } else {
+            matches
+                .iter()
+                .rev()
+                .find(|m| m.start < sel.start)
+                .unwrap_or(last)
+        };
+        sel.start = m.start;
+        sel.end = m.end.saturating_sub(1).max(m.start);
+        sel.dir = Direction::Forward;
+    }
+
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(buffer, scrolloff);
+    state.search_count = SearchCount::count(state);
+    Ok(())
+}
+
+fn keep_primary_selection(engine: Engine) {
+    {
+        let mut state = engine.state_mut();
+        let state = &mut *state;
+        let view = state.views.get_mut(&state.active_view).unwrap();
+        let Some(&primary) = view.primary_selection() else {
+            return;
+        };
+        view.selections = vec![primary];
+        view.primary_index = 0;
+    }
+    for_selection_mut(engine, |sel, _| collapse_cursor(sel));
+}
+
+fn collapse_selections(engine: Engine) {
+    for_selection_mut(engine.clone(), |sel, _| collapse_cursor(sel));
+    // Cursors that end up on the same char become one
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let view = state.views.get_mut(&state.active_view).unwrap();
+    view.merge_overlapping_selections();
+}
+
+/// Keeps the selections whose text matches `pattern`, or drops them if `keep` is
+/// false. If nothing would be left, only the primary selection is kept.
+fn filter_selections(engine: Engine, pattern: String, keep: bool) -> anyhow::Result<()> {
+    let regex = Regex::new(&pattern)?;
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let (mut view, buffer) = view_buffer(state);
+
+    let kept = view
+        .selections
+        .iter()
+        .map(|sel| {
+            let end = (sel.end + 1).min(buffer.contents.len_chars());
+            let text = buffer.contents.slice(sel.start.min(end)..end).to_string();
+            regex.is_match(&text) == keep
+        })
+        .collect::<Vec<_>>();
+
+    if !kept.contains(&true) {
+        let primary = view.selections[view.primary_index];
+        view.selections = vec![primary];
+        view.primary_index = 0;
+    } else {
+        // The primary moves to the closest kept selection before it, if it goes
+        let primary = kept[..=view.primary_index]
+            .iter()
+            .filter(|k| **k)
+            .count()
+            .saturating_sub(1);
+        let mut kept = kept.into_iter();
+        view.selections.retain(|_| kept.next().unwrap());
+        view.primary_index = primary;
+    }
+
+    view.make_selection_visisble(&buffer, scrolloff);
+    Ok(())
+}
+
+/// Points the buffer at a new file, returning the previous backing
+fn set_file_backing(buffer: &mut Buffer, path: PathBuf) -> BufferBacking {
+    buffer.name = path.to_string_lossy().to_string();
+    std::mem::replace(&mut buffer.backing, BufferBacking::File(path))
+}
+
+fn write(engine: Engine, args: Vec<CommandArg>, force: bool) -> anyhow::Result<()> {
+    let trim = engine.state().editor_options.trim_on_write;
+    let (mut view, mut buffer) = view_buffer(engine.state_mut());
+    if let Some(path) = args.into_iter().next() {
+        let path: String = path.into();
+        let path = PathBuf::from(path);
+        if !matches!(&buffer.backing, BufferBacking::File(old) if same_file(old, &path)) {
+            buffer.disk_stamp = None;
+        }
+        set_file_backing(&mut buffer, path);
+    }
+    if matches!(buffer.backing, BufferBacking::None) {
+        anyhow::bail!("{} has no file; use write <path>", buffer.name);
+    }
+    if trim {
+        trim_trailing_whitespace(&mut view, &mut buffer);
+    }
+    drop(view);
+    buffer.save(force)?;
+
+    if let BufferBacking::File(path) = &buffer.backing {
+        let msg = format!("Wrote {}", path.display());
+        drop(buffer);
+        engine.state_mut().message(MessageLevel::Info, msg);
+    }
+    Ok(())
+}
+
+fn reload_buffer(engine: Engine, force: bool) -> anyhow::Result<()> {
+    let mut state = engine.state_mut();
+    let id = state.views[&state.active_view].buffer;
+    let buffer = &state.buffers[&id];
+    if !force && buffer.is_modified() {
+        anyhow::bail!(
+            "{} has unsaved changes; use reload-buffer-force to discard them",
+            buffer.name
+        );
+    }
+    state.reload(id)
+}
+
+/// Replaces the buffer contents with its recovery file, as an undoable edit
+fn recover(engine: Engine) -> anyhow::Result<()> {
+    let state = engine.state_mut();
+    let scrolloff = state.active_options().scrolloff;
+    let (mut view, mut buffer) = view_buffer(state);
+    let Some(path) = buffer.recovery_file() else {
+        anyhow::bail!("No recovery file for {}", buffer.name);
+    };
+    let text = std::fs::read_to_string(&path)?;
+
+    let mut actions = vec![];
+    let len = buffer.contents.len_chars();
+    replace_range(&mut buffer, &mut view, 0, len, text, &mut actions);
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(&buffer, scrolloff);
+
+    let msg = format!("Restored {}; write to keep the changes", path.display());
+    drop((view, buffer));
+    engine.state_mut().message(MessageLevel::Info, msg);
+    Ok(())
+}
+
+fn discard_recovery(engine: Engine) -> anyhow::Result<()> {
+    let (_, buffer) = view_buffer(engine.state_mut());
+    let Some(path) = buffer.recovery_file() else {
+        anyhow::bail!("No recovery file for {}", buffer.name);
+    };
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+/// Writes the buffer to `path` and makes it the new backing file, optionally
+/// removing the previous one. An existing file other than the buffer's own is
+/// only overwritten when `force` is set.
+fn save_as(engine: Engine, path: String, remove_old: bool, force: bool) -> anyhow::Result<()> {
+    let (_, mut buffer) = view_buffer(engine.state_mut());
+    let path = PathBuf::from(path);
+    // The same file under another spelling must neither count as overwriting
+    // nor be removed afterwards
+    let same = matches!(&buffer.backing, BufferBacking::File(old) if same_file(old, &path));
+    if !force && !same && path.exists() {
+        let command = if remove_old { "rename" } else { "save-as" };
+        anyhow::bail!(
+            "{} already exists; use {command}-force to overwrite it",
+            path.display()
+        );
+    }
+
+    BufferBacking::File(path.clone()).save(&buffer.contents)?;
+    buffer.disk_stamp = DiskStamp::read(&path);
+    buffer.saved_version = buffer.version;
+    let old = set_file_backing(&mut buffer, path.clone());
+    buffer.reload_diff_base();
+
+    if remove_old
+        && !same
+        && let BufferBacking::File(old) = old
+    {
+        std::fs::remove_file(old)?;
+    }
+
+    drop(buffer);
+    engine
+        .state_mut()
+        .message(MessageLevel::Info, format!("Wrote {}", path.display()));
+    Ok(())
+}
+
+fn goto_end_of_line(engine: Engine, collapse: bool) {
+    for_selection_mut(engine, |sel, buf| {
+        let (line, col) = get_head_pos(sel, buf);
+        set_head_pos(sel, buf, line, usize::MAX);
+        if collapse {
+            collapse_cursor(sel);
+        }
+        sel.make_valid(&buf.contents);
+    });
+}
+
+fn goto_start_of_line(engine: Engine, collapse: bool) {
+    for_selection_mut(engine, |sel, buf| {
+        let (line, col) = get_head_pos(sel, buf);
+        set_head_pos(sel, buf, line, 0);
+        if collapse {
+            collapse_cursor(sel);
+        }
+        sel.make_valid(&buf.contents);
+    });
+}
+
+/// Moves each head past the next (or previous) paragraph, to the blank line
+/// after (or before) it, or to the end (or start) of the buffer if there is none
+fn goto_paragraph(engine: Engine, forward: bool, collapse: bool) {
+    for_selection_mut(engine, |sel, buf| {
+        let text = &buf.contents;
+        let is_blank = |line: usize| text.line(line).chars().all(char::is_whitespace);
+        let last = text.len_lines() - 1;
+        let (mut line, _) = get_head_pos(sel, buf);
+
+        if forward {
+            while line < last && is_blank(line) {
+                line += 1;
+            }
+            while line < last && !is_blank(line) {
+                line += 1;
+            }
+            let col = if is_blank(line) { 0 } else { usize::MAX };
+            set_head_pos(sel, buf, line, col);
+        } else {
+            while line > 0 && is_blank(line) {
+                line -= 1;
+            }
+            while line > 0 && !is_blank(line) {
+                line -= 1;
+            }
+            set_head_pos(sel, buf, line, 0);
+        }
+        if collapse {
+            collapse_cursor(sel);
+        }
+    });
+}
+
+/// A `find-char` style motion, remembered so that it can be repeated
+#[derive(Clone, Copy)]
+pub struct CharFind {
+    pub c: char,
+    /// Stop next to the char instead of on it
+    pub till: bool,
+    pub backward: bool,
+}
+
+/// Moves each head to the next occurrence of the char on its line, leaving
+/// selections without one where they are
+fn find_char(engine: Engine, find: CharFind, collapse: bool) {
+    for_selection_mut(engine, |sel, buf| {
+        let text = &buf.contents;
+        let head = sel.head().min(text.len_chars());
+        let line = text.char_to_line(head);
+        let line_start = text.line_to_char(line);
+        let line_end = line_start + text.line(line).len_chars();
+
+        // Skip the char next to the head when tilling, so repeating moves on
+        let skip = if find.till { 2 } else { 1 };
+        let target = if find.backward {
+            (line_start..(head + 1).saturating_sub(skip))
+                .rev()
+                .find(|&i| text.char(i) == find.c)
+                .map(|i| if find.till { i + 1 } else { i })
+        } else {
+            (head + skip..line_end)
+                .find(|&i| text.char(i) == find.c)
+                .map(|i| if find.till { i - 1 } else { i })
+        };
+
+        if let Some(target) = target {
+            *sel.head_mut() = target;
+            if collapse {
+                collapse_cursor(sel);
+            }
+            sel.make_valid(text);
+        }
+    });
+}
+
+/// The single char argument of the command `name`. Without arguments, `name`
+/// is run again with the next key typed, and `None` is returned.
+fn char_arg(engine: &Engine, name: &str, args: Vec<CommandArg>) -> anyhow::Result<Option<char>> {
+    let Some(arg) = args.into_iter().next() else {
+        engine.state_mut().char_prompt = Some((name.into(), vec![]));
+        return Ok(None);
+    };
+    let arg = String::from(arg);
+    let mut chars = arg.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        anyhow::bail!("{name} expects a single character");
+    };
+    Ok(Some(c))
 }
 
-fn insert(engine: Engine, text: String) {
-    let state = engine.state_mut();
-    let (mut view, mut buffer) = view_buffer(state);
+/// Inserts the char with the given hexadecimal code point, e.g. `1F600`
+fn insert_unicode(engine: Engine, hex: String) -> anyhow::Result<()> {
+    let hex = hex.trim_start_matches("U+").trim_start_matches("u+");
+    let Ok(code) = u32::from_str_radix(hex, 16) else {
+        anyhow::bail!("insert-unicode expects a hexadecimal code point, not {hex}");
+    };
+    let Some(c) = char::from_u32(code) else {
+        anyhow::bail!("U+{code:04X} is not a valid character");
+    };
+    insert(engine, c.to_string());
+    Ok(())
+}
 
-    let mut actions = vec![];
+/// Inserts the char for a digraph, prompting for whichever of its two chars
+/// weren't given
+fn insert_digraph(engine: Engine, args: Vec<CommandArg>) -> anyhow::Result<()> {
+    let chars = args
+        .into_iter()
+        .map(|arg| {
+            let arg = String::from(arg);
+            let mut chars = arg.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => anyhow::bail!("insert-digraph expects single characters"),
+            }
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (first, second) = match chars[..] {
+        [first, second] => (first, second),
+        [] | [_] => {
+            let args = chars
+                .iter()
+                .map(|c| CommandArg::String(c.to_string()))
+                .collect();
+            engine.state_mut().char_prompt = Some(("insert-digraph".into(), args));
+            return Ok(());
+        }
+        _ => anyhow::bail!("insert-digraph expects at most two characters"),
+    };
+    let Some(c) = digraph::lookup(first, second) else {
+        anyhow::bail!("No digraph for {first}{second}");
+    };
+    insert(engine, c.to_string());
+    Ok(())
+}
 
-    for i in 0..view.selections.len() {
-        let s = view.selections[i];
-        buffer.insert(&mut view, &text, s.start);
-        let action = Action::TextInsertion {
-            text: text.clone(),
-            start: s.start,
+/// A char-find motion taking the char as argument, or else from the next key typed
+fn char_find_command(
+    name: &'static str,
+    desc: &'static str,
+    till: bool,
+    backward: bool,
+    collapse: bool,
+) -> Command {
+    Command::new(name, desc, move |engine: Engine, args: Vec<CommandArg>| {
+        let Some(c) = char_arg(&engine, name, args)? else {
+            return Ok(());
         };
-        actions.push(action);
-    }
-
-    buffer.history.register_edit(HistoryAction { actions });
-    buffer.recalc_tree();
-
-    view.make_selection_visisble(&buffer);
+        let find = CharFind { c, till, backward };
+        engine.state_mut().last_char_find = Some(find);
+        find_char(engine, find, collapse);
+        Ok(())
+    })
 }
 
-fn goto_end_of_line(engine: Engine, collapse: bool) {
-    for_selection_mut(engine, |sel, buf| {
-        let (line, col) = get_head_pos(sel, buf);
-        set_head_pos(sel, buf, line, usize::MAX);
-        if collapse {
-            collapse_cursor(sel);
-        }
-        sel.make_valid(&buf.contents);
-    });
+/// Repeats the last char-find motion, in the opposite direction if `reverse` is set
+fn repeat_char_find(engine: Engine, reverse: bool) -> anyhow::Result<()> {
+    let Some(mut find) = engine.state().last_char_find else {
+        anyhow::bail!("No previous char find");
+    };
+    find.backward ^= reverse;
+    find_char(engine, find, true);
+    Ok(())
 }
 
-fn goto_start_of_line(engine: Engine, collapse: bool) {
+/// Moves to the first non-blank char of the line, or to column 0 if already there
+fn goto_start_of_line_smart(engine: Engine, collapse: bool) {
     for_selection_mut(engine, |sel, buf| {
         let (line, col) = get_head_pos(sel, buf);
-        set_head_pos(sel, buf, line, 0);
+        let first_non_blank = buf
+            .contents
+            .line(line)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        let col = if col == first_non_blank {
+            0
+        } else {
+            first_non_blank
+        };
+        set_head_pos(sel, buf, line, col);
         if collapse {
             collapse_cursor(sel);
         }
@@ -266,6 +1672,55 @@ fn goto_start(engine: Engine, collapse: bool) {
     });
 }
 
+/// Moves the primary selection to the start of the line `percent`% into the
+/// buffer and centers it in the view
+fn goto_percent(engine: Engine, percent: i32) {
+    let (mut view, buffer) = view_buffer(engine.state_mut());
+    let text = &buffer.contents;
+    let percent = percent.clamp(0, 100) as usize;
+    let line = text.len_lines().saturating_sub(1) * percent / 100;
+    let pos = text.line_to_char(line);
+
+    let primary = view.primary_index;
+    if let Some(sel) = view.selections.get_mut(primary) {
+        sel.start = pos;
+        sel.end = pos;
+        sel.make_valid(text);
+    }
+    view.merge_overlapping_selections();
+    view.center_selection(&buffer);
+}
+
+/// Moves the primary selection to the first line of the next or previous
+/// changed hunk, wrapping around at the ends of the buffer
+fn goto_change(engine: Engine, forward: bool) -> anyhow::Result<()> {
+    let (mut view, mut buffer) = view_buffer(engine.state_mut());
+    if buffer.undiffed_edit.is_some() {
+        buffer.update_diff();
+    }
+    let primary = view.primary_index;
+    let Some(sel) = view.selections.get_mut(primary) else {
+        return Ok(());
+    };
+    let (line, _) = get_head_pos(sel, &buffer);
+
+    let starts = buffer.diff_hunks.iter().map(|h| h.lines.start);
+    let target = if forward {
+        starts.clone().find(|&l| l > line).or(starts.min())
+    } else {
+        starts.clone().rev().find(|&l| l < line).or(starts.max())
+    };
+    let Some(target) = target else {
+        anyhow::bail!("No changes in {}", buffer.name);
+    };
+
+    set_head_pos(sel, &buffer, target, 0);
+    collapse_cursor(sel);
+    view.merge_overlapping_selections();
+    view.center_selection(&buffer);
+    Ok(())
+}
+
 fn goto_end(engine: Engine, collapse: bool) {
     for_selection_mut(engine, |sel, buf| {
         let len = buf.contents.len_chars();
@@ -281,26 +1736,30 @@ fn goto_end(engine: Engine, collapse: bool) {
 fn undo(engine: Engine) {
     let mut state = engine.state_mut();
     let state = &mut *state;
-    let view = state.views.get_mut(&state.active_view).unwrap();
+    let scrolloff = state.active_options().scrolloff;
+    let mut view = ViewMut::new(&mut state.views, state.active_view);
     let buffer = state.buffers.get_mut(&view.buffer).unwrap();
 
-    buffer.undo(view);
+    buffer.undo(&mut view);
+    view.make_selection_visisble(buffer, scrolloff);
 }
 
 fn redo(engine: Engine) {
     let mut state = engine.state_mut();
     let state = &mut *state;
-    let view = state.views.get_mut(&state.active_view).unwrap();
+    let scrolloff = state.active_options().scrolloff;
+    let mut view = ViewMut::new(&mut state.views, state.active_view);
     let buffer = state.buffers.get_mut(&view.buffer).unwrap();
 
-    buffer.redo(view);
+    buffer.redo(&mut view);
+    view.make_selection_visisble(buffer, scrolloff);
 }
 
 fn show_kill_ring(engine: Engine) {
     let mut state = engine.state_mut();
     let buffer_id = state.create_buffer();
     let view_id = state.create_view(buffer_id);
-    state.active_view = view_id;
+    state.set_active_view(view_id);
 
     let mut contents = String::new();
     for entry in &state.kill_ring.entries {
@@ -323,43 +1782,65 @@ fn copy_kill_ring(engine: Engine) {
     let view = state.views.get_mut(&active_view).unwrap();
     let buffer = state.buffers.get_mut(&view.buffer).unwrap();
 
-    state
-        .kill_ring
-        .add_entry(KillRingEntry::new(view.selections.iter().map(
-            |selection| {
-                buffer
-                    .contents
-                    .slice(selection.start..(selection.end + 1).min(buffer.contents.len_chars()))
-                    .to_string()
-            },
-        )));
+    let linewise = view
+        .selections
+        .iter()
+        .all(|s| is_linewise(s, &buffer.contents));
+    state.kill_ring.add_entry(
+        KillRingEntry::new(view.selections.iter().map(|selection| {
+            buffer
+                .contents
+                .slice(selection.start..(selection.end + 1).min(buffer.contents.len_chars()))
+                .to_string()
+        }))
+        .linewise(linewise),
+        state.editor_options.kill_ring_max,
+    );
 }
 
 fn paste_kill_ring(engine: Engine, before: bool) {
     let mut state = engine.state_mut();
     let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
 
     if state.kill_ring.entries.is_empty() {
         return;
     }
 
-    let view = state.views.get_mut(&state.active_view).unwrap();
+    let mut view = ViewMut::new(&mut state.views, state.active_view);
     let buffer = state.buffers.get_mut(&view.buffer).unwrap();
 
     let mut actions = vec![];
 
-    let texts = state
-        .kill_ring
-        .get()
-        .unwrap()
-        .get_for_cursor_count(view.selections.len());
+    let entry = state.kill_ring.get().unwrap();
+    let texts = entry.get_for_cursor_count(view.selections.len());
 
     for i in 0..view.selections.len() {
         let s = view.selections[i];
-        let start = (if before { s.start } else { s.end + 1 }).min(buffer.contents.len_chars());
-        buffer.insert(view, texts[i], start);
+        let text = &buffer.contents;
+        let mut insertion = texts[i].to_string();
+        let start = if entry.linewise {
+            // Whole lines go above or below the lines of the selection
+            let line = if before {
+                text.char_to_line(s.start.min(text.len_chars()))
+            } else {
+                text.char_to_line(s.end.min(text.len_chars())) + 1
+            };
+            let start = text.line_to_char(line.min(text.len_lines()));
+            if start == text.len_chars() && start > 0 && text.char(start - 1) != '\n' {
+                // The last line has no newline to paste after
+                if insertion.ends_with('\n') {
+                    insertion.pop();
+                }
+                insertion.insert(0, '\n');
+            }
+            start
+        } else {
+            (if before { s.start } else { s.end + 1 }).min(text.len_chars())
+        };
+        buffer.insert(&mut view, &insertion, start);
         let action = Action::TextInsertion {
-            text: texts[0].to_string(),
+            text: insertion,
             start,
         };
         actions.push(action);
@@ -368,27 +1849,187 @@ fn paste_kill_ring(engine: Engine, before: bool) {
     buffer.history.register_edit(HistoryAction { actions });
     buffer.recalc_tree();
 
-    view.make_selection_visisble(buffer);
+    view.make_selection_visisble(buffer, scrolloff);
 }
 
 fn close_buffer(engine: Engine) {
     let mut state = engine.state_mut();
-    let state = &mut *state;
     let view = state.active_view;
-    let view = state.views.remove(&view).unwrap();
-    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
-    buffer.view_count -= 1;
-    if buffer.view_count == 0 {
-        state.buffers.remove(&view.buffer).unwrap();
+    state.remove_view(view);
+    state.activate_remaining_view();
+}
+
+fn alternate_buffer(engine: Engine) -> anyhow::Result<()> {
+    let mut state = engine.state_mut();
+    let Some(view) = state.alternate_view() else {
+        anyhow::bail!("No other view to switch to");
+    };
+    state.set_active_view(view);
+    Ok(())
+}
+
+/// The buffer with the id or name given, as shown by `list-buffers`
+fn find_buffer(state: &EngineState, arg: &CommandArg) -> anyhow::Result<BufferId> {
+    match arg {
+        CommandArg::Integer(id) => usize::try_from(*id)
+            .ok()
+            .map(BufferId)
+            .filter(|id| state.buffers.contains_key(id))
+            .ok_or_else(|| anyhow::anyhow!("No buffer with id {id}")),
+        CommandArg::String(name) => state
+            .buffers
+            .values()
+            .find(|buffer| buffer.name == *name)
+            .map(|buffer| buffer.id)
+            .ok_or_else(|| anyhow::anyhow!("No buffer named {name}")),
+        CommandArg::Bool(_) => anyhow::bail!("Expected a buffer id or name"),
     }
+}
 
-    state.active_view = match state.views.keys().next() {
-        Some(id) => *id,
+fn diff(engine: Engine, left: CommandArg, right: CommandArg) -> anyhow::Result<()> {
+    let mut state = engine.state_mut();
+    let left = find_buffer(&state, &left)?;
+    let right = find_buffer(&state, &right)?;
+    // An open diff shows the new buffers in its views instead of opening more
+    let left = match &state.diff {
+        Some(diff) => {
+            let (left_view, right_view) = (diff.left, diff.right);
+            state.set_view_buffer(left_view, left)?;
+            state.set_view_buffer(right_view, right)?;
+            left_view
+        }
         None => {
-            let buffer = state.create_buffer();
-            state.create_view(buffer)
+            let previous = state.active_view;
+            let left = state.create_view(left);
+            let right = state.create_view(right);
+            state.diff = Some(DiffSplit::new(left, right, previous));
+            left
+        }
+    };
+    state.set_active_view(left);
+    state.layout_views();
+    Ok(())
+}
+
+fn lock_scroll(engine: Engine) -> anyhow::Result<()> {
+    let mut state = engine.state_mut();
+    let panes = state.visible_views();
+    if panes.len() < 2 {
+        anyhow::bail!("No other pane to lock scrolling with");
+    }
+    let group = state.views[&panes[0]].scroll_lock_group;
+    let locked = group.is_some()
+        && panes
+            .iter()
+            .all(|view| state.views[view].scroll_lock_group == group);
+    let unused_group = state
+        .views
+        .values()
+        .filter_map(|view| view.scroll_lock_group)
+        .max()
+        .map_or(0, |group| group + 1);
+    let group = (!locked).then_some(unused_group);
+    for view in &panes {
+        state.views.get_mut(view).unwrap().scroll_lock_group = group;
+    }
+    let active = state.active_view;
+    state.scroll_group_to(active);
+    Ok(())
+}
+
+/// Number of single-char insertions, deletions, substitutions and swaps of
+/// adjacent chars turning `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    // d[i][j] is the distance between the first i chars of a and first j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Up to three command names close to `name`, closest first
+pub fn similar_commands(state: &EngineState, name: &str) -> Vec<String> {
+    let max_distance = (name.chars().count() / 3).clamp(1, 3);
+    let mut similar = state
+        .commands
+        .keys()
+        .map(|cmd| (edit_distance(name, cmd), cmd))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect::<Vec<_>>();
+    similar.sort();
+    similar
+        .into_iter()
+        .take(3)
+        .map(|(_, cmd)| cmd.clone())
+        .collect()
+}
+
+/// Error for running a command that doesn't exist, suggesting similar ones
+pub fn unknown_command(state: &EngineState, name: &str) -> anyhow::Error {
+    let similar = similar_commands(state, name)
+        .iter()
+        .map(|cmd| format!("'{cmd}'"))
+        .collect::<Vec<_>>();
+    match &similar[..] {
+        [] => anyhow::anyhow!("Unknown command '{name}'"),
+        [only] => anyhow::anyhow!("Unknown command '{name}'; did you mean {only}?"),
+        [rest @ .., last] => anyhow::anyhow!(
+            "Unknown command '{name}'; did you mean {} or {last}?",
+            rest.join(", ")
+        ),
+    }
+}
+
+fn help(engine: Engine, name: String) -> anyhow::Result<()> {
+    let mut state = engine.state_mut();
+    let Some(command) = state.commands.get(&name) else {
+        return Err(unknown_command(&state, &name));
+    };
+
+    use std::fmt::Write;
+    let mut contents = String::new();
+    match command.signature().as_deref() {
+        Some("") => writeln!(&mut contents, "{name}").unwrap(),
+        Some(signature) => writeln!(&mut contents, "{name} {signature}").unwrap(),
+        None => writeln!(&mut contents, "{name} [args...]").unwrap(),
+    }
+    writeln!(&mut contents).unwrap();
+    writeln!(&mut contents, "{}", command.desc).unwrap();
+    writeln!(&mut contents).unwrap();
+    let bound = state.keybinds.sequences_for(&name);
+    if bound.is_empty() {
+        writeln!(&mut contents, "Not bound to any keys").unwrap();
+    } else {
+        writeln!(&mut contents, "Bound to:").unwrap();
+        for (mode, seq) in bound {
+            writeln!(&mut contents, "    {mode}: {seq}").unwrap();
         }
     }
+
+    let mut buffer = Buffer::create_from_contents("*help*".into(), contents.into());
+    buffer.language = "text".into();
+    let buffer_id = buffer.id;
+    state.buffers.insert(buffer_id, buffer);
+    let view = state.create_view(buffer_id);
+    state.set_active_view(view);
+    Ok(())
 }
 
 fn list_buffers(engine: Engine) {
@@ -396,7 +2037,7 @@ fn list_buffers(engine: Engine) {
     let state = &mut *state;
     let buffer_id = state.create_buffer();
     let view_id = state.create_view(buffer_id);
-    state.active_view = view_id;
+    state.set_active_view(view_id);
 
     let mut contents = String::new();
     for (id, buffer) in &state.buffers {
@@ -411,6 +2052,7 @@ fn list_buffers(engine: Engine) {
 fn tree_sitter_out(engine: Engine) {
     let mut state = engine.state_mut();
     let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
     let view = state.views.get_mut(&state.active_view).unwrap();
     let buffer = state.buffers.get_mut(&view.buffer).unwrap();
 
@@ -436,12 +2078,13 @@ fn tree_sitter_out(engine: Engine) {
     }
 
     view.merge_overlapping_selections();
-    view.make_selection_visisble(buffer);
+    view.make_selection_visisble(buffer, scrolloff);
 }
 
 fn tree_sitter_in(engine: Engine) {
     let mut state = engine.state_mut();
     let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
     let view = state.views.get_mut(&state.active_view).unwrap();
     let buffer = state.buffers.get_mut(&view.buffer).unwrap();
 
@@ -464,12 +2107,13 @@ fn tree_sitter_in(engine: Engine) {
     }
 
     view.merge_overlapping_selections();
-    view.make_selection_visisble(buffer);
+    view.make_selection_visisble(buffer, scrolloff);
 }
 
 fn tree_sitter_next(engine: Engine) {
     let mut state = engine.state_mut();
     let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
     let view = state.views.get_mut(&state.active_view).unwrap();
     let buffer = state.buffers.get_mut(&view.buffer).unwrap();
 
@@ -492,12 +2136,13 @@ fn tree_sitter_next(engine: Engine) {
     }
 
     view.merge_overlapping_selections();
-    view.make_selection_visisble(buffer);
+    view.make_selection_visisble(buffer, scrolloff);
 }
 
 fn tree_sitter_prev(engine: Engine) {
     let mut state = engine.state_mut();
     let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
     let view = state.views.get_mut(&state.active_view).unwrap();
     let buffer = state.buffers.get_mut(&view.buffer).unwrap();
 
@@ -520,7 +2165,150 @@ fn tree_sitter_prev(engine: Engine) {
     }
 
     view.merge_overlapping_selections();
-    view.make_selection_visisble(buffer);
+    view.make_selection_visisble(buffer, scrolloff);
+}
+
+/// Toggles `flag`, or sets it if a bool argument is given
+fn toggle(flag: &mut bool, args: Vec<CommandArg>) -> anyhow::Result<()> {
+    *flag = match args.into_iter().next() {
+        Some(arg) => arg.try_into()?,
+        None => !*flag,
+    };
+    Ok(())
+}
+
+/// Removes the whitespace at the end of every line as one undoable edit.
+/// Selections in removed whitespace move to the end of their line's text, and
+/// nothing scrolls.
+fn trim_trailing_whitespace(view: &mut ViewMut<impl Views>, buffer: &mut Buffer) {
+    let mut actions = vec![];
+    // Bottom up, so that the lines still to be trimmed keep their positions
+    for line in (0..buffer.contents.len_lines()).rev() {
+        let text = buffer.contents.line(line);
+        let content_len = text.chars().take_while(|c| *c != '\n').count();
+        let trimmed_len = text
+            .chars()
+            .take(content_len)
+            .collect::<String>()
+            .trim_end_matches([' ', '\t'])
+            .chars()
+            .count();
+        let len = content_len - trimmed_len;
+        if len == 0 {
+            continue;
+        }
+        let start = buffer.contents.line_to_char(line) + trimmed_len;
+        let deleted_text = buffer.contents.slice(start..start + len).to_string();
+        buffer.remove(view, start, len);
+        actions.push(Action::TextDeletion {
+            deleted_text,
+            start,
+            len,
+        });
+    }
+
+    if actions.is_empty() {
+        return;
+    }
+    buffer.history.register_edit(HistoryAction { actions });
+    buffer.recalc_tree();
+}
+
+fn fold(engine: Engine) {
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let view = state.views.get_mut(&state.active_view).unwrap();
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    for sel in &view.selections {
+        let head = buffer.contents.char_to_byte(sel.head());
+        let mut node = buffer
+            .tree
+            .root_node()
+            .descendant_for_byte_range(head, head);
+
+        // Find the smallest node spanning multiple lines
+        while let Some(n) = node {
+            let range = n.byte_range();
+            let start_line = buffer.contents.byte_to_line(range.start);
+            let end_line = buffer.contents.byte_to_line(range.end.saturating_sub(1));
+            if end_line > start_line {
+                view.folds.add(start_line..=end_line);
+                break;
+            }
+            node = n.parent();
+        }
+    }
+
+    view.make_selection_visisble(buffer, scrolloff);
+}
+
+fn unfold(engine: Engine) {
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let view = state.views.get_mut(&state.active_view).unwrap();
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    for sel in &view.selections {
+        let line = buffer.contents.char_to_line(sel.head());
+        view.folds.remove_containing(line);
+    }
+
+    view.make_selection_visisble(buffer, scrolloff);
+}
+
+fn select_enclosing(engine: Engine, category: &str) {
+    let mut state = engine.state_mut();
+    let state = &mut *state;
+    let scrolloff = state.active_options().scrolloff;
+    let view = state.views.get_mut(&state.active_view).unwrap();
+    let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+
+    let Some(kinds) = state
+        .node_kinds
+        .get(&(buffer.language.clone(), category.to_string()))
+    else {
+        warn!(
+            "No {category} node kinds configured for {}",
+            buffer.language
+        );
+        return;
+    };
+
+    for sel in &mut view.selections {
+        let start = buffer.contents.char_to_byte(sel.start);
+        let end = buffer
+            .contents
+            .char_to_byte((sel.end + 1).min(buffer.contents.len_chars()));
+        let mut node = buffer
+            .tree
+            .root_node()
+            .descendant_for_byte_range(start, end);
+
+        // Skip the node matching the current selection exactly, so that
+        // repeated invocations expand outwards
+        while let Some(n) = node {
+            let range = n.byte_range();
+            if kinds.iter().any(|k| k == n.kind()) && (range.start < start || range.end > end) {
+                break;
+            }
+            node = n.parent();
+        }
+
+        match node {
+            Some(node) => {
+                let range = node.byte_range();
+                sel.start = buffer.contents.byte_to_char(range.start);
+                sel.end = buffer.contents.byte_to_char(range.end) - 1;
+            }
+            None => debug!("No enclosing {category} found for selection"),
+        }
+    }
+
+    view.merge_overlapping_selections();
+    view.make_selection_visisble(buffer, scrolloff);
 }
 
 pub fn builtin_commands() -> impl Iterator<Item = Command> {
@@ -529,7 +2317,17 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
             "enter-command-mode",
             "Enter command mode",
             |engine: Engine| {
-                engine.state_mut().cli.focus = true;
+                engine.state_mut().cli.open(":", PromptTarget::Commands);
+            },
+        ),
+        Command::new(
+            "prompt",
+            "Read a line in the command line after the given prompt, then run the given command with it",
+            |engine: Engine, prompt: String, command: String| {
+                engine
+                    .state_mut()
+                    .cli
+                    .open(prompt, PromptTarget::Command(command));
             },
         ),
         Command::new(
@@ -567,36 +2365,250 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
             },
         ),
         Command::new(
-            "extend-char-down",
-            "Extend selection one char down",
-            |engine: Engine| {
-                move_char_down(engine);
-            },
+            "extend-char-down",
+            "Extend selection one char down",
+            |engine: Engine| {
+                move_char_down(engine);
+            },
+        ),
+        Command::new(
+            "extend-char-up",
+            "Extend selection one char up",
+            |engine: Engine| {
+                move_char_up(engine);
+            },
+        ),
+        Command::new("delete", "Delete selected text", |engine: Engine| {
+            delete(engine);
+        }).edits(),
+        Command::new(
+            "select-line",
+            "Extend selections to whole lines, or to the next line",
+            select_line,
+        ),
+        Command::new(
+            "delete-line",
+            "Delete the lines covered by each selection",
+            |engine: Engine| {
+                select_line(engine.clone());
+                delete(engine);
+            },
+        ).edits(),
+        Command::new(
+            "indent",
+            "Indent the lines covered by each selection",
+            |engine: Engine| {
+                indent(engine);
+            },
+        ).edits(),
+        Command::new(
+            "dedent",
+            "Dedent the lines covered by each selection",
+            |engine: Engine| {
+                dedent(engine);
+            },
+        ).edits(),
+        Command::new(
+            "insert-indent",
+            "Insert one level of indentation before each selection",
+            |engine: Engine| {
+                let unit = engine.state().active_options().indent_unit();
+                insert(engine, unit);
+            },
+        ).edits(),
+        Command::new(
+            "backspace",
+            "Delete character before selection, or undo typing in overwrite mode",
+            |engine: Engine| {
+                if engine.state().overwrite {
+                    overwrite_backspace(engine);
+                } else {
+                    backspace(engine);
+                }
+            },
+        ).edits(),
+        Command::new(
+            "delete-word-backward",
+            "Delete the word before each selection",
+            delete_word_backward,
+        ).edits(),
+        Command::new(
+            "transpose-chars",
+            "Swap the chars around each cursor",
+            transpose_chars,
+        ).edits(),
+        Command::new(
+            "transpose-words",
+            "Swap the word at each cursor with the next one",
+            transpose_words,
+        ).edits(),
+        Command::new(
+            "set-overwrite",
+            "Toggle overwrite mode, or set it to the given value",
+            |engine: Engine, args: Vec<CommandArg>| {
+                let mut state = engine.state_mut();
+                state.overwritten.clear();
+                toggle(&mut state.overwrite, args)
+            },
+        ),
+        Command::new(
+            "insert",
+            "Insert given text before each selection, optionally repeated a number of times",
+            |engine: Engine, args: Vec<CommandArg>| {
+                let mut args = args.into_iter();
+                let text = match (args.next(), args.next(), args.next()) {
+                    (Some(text), None, None) => String::from(text),
+                    (Some(count), Some(text), None) => {
+                        let count: i32 = count.try_into()?;
+                        String::from(text).repeat(count.max(0) as usize)
+                    }
+                    _ => anyhow::bail!("Usage: insert [count] <text>"),
+                };
+                insert(engine, text);
+                Ok(())
+            },
+        ).edits(),
+        Command::new(
+            "insert-char",
+            "Insert a char as if typed in insert mode",
+            |engine: Engine, c: String| {
+                let Some(c) = c.chars().next() else {
+                    anyhow::bail!("insert-char expects a character");
+                };
+                insert_char(engine, c);
+                Ok(())
+            },
+        ).arg_names(&["char"]).edits(),
+        Command::new(
+            "insert-unicode",
+            "Insert the character with the given hexadecimal code point",
+            insert_unicode,
+        ).arg_names(&["code-point"]).edits(),
+        Command::new(
+            "insert-digraph",
+            "Insert the character for a two-character digraph, read from the next keys if not given",
+            insert_digraph,
+        ).edits(),
+        Command::new(
+            "set-option",
+            "Set an editor option",
+            |engine: Engine, name: String, value: CommandArg| {
+                engine.state_mut().editor_options.set(&name, value)
+            },
+        ).arg_names(&["option", "value"]),
+        Command::new(
+            "set-buffer-option",
+            "Set an option for the current buffer only",
+            |engine: Engine, name: String, value: CommandArg| {
+                let (_, mut buffer) = view_buffer(engine.state_mut());
+                buffer.options.set(&name, Some(value))
+            },
+        ).arg_names(&["option", "value"]),
+        Command::new(
+            "set-auto-pairs",
+            "Toggle auto-pairs, or set it to the given value",
+            |engine: Engine, args: Vec<CommandArg>| {
+                toggle(&mut engine.state_mut().editor_options.auto_pairs, args)
+            },
+        ),
+        Command::new(
+            "search",
+            "Select the next match of the given regex for each selection",
+            |engine: Engine, pattern: String| {
+                Regex::new(&pattern)?;
+                let mut state = engine.state_mut();
+                state.last_search = Some(pattern);
+                state.search_count = None;
+                drop(state);
+                search_next(engine, true)
+            },
+        ).arg_names(&["regex"]),
+        Command::new(
+            "selection-undo",
+            "Restore the selections from before the last selection change",
+            |engine: Engine| {
+                let state = engine.state_mut();
+                let scrolloff = state.active_options().scrolloff;
+                let (mut view, buffer) = view_buffer(state);
+                if !view.undo_selections(&buffer.contents) {
+                    anyhow::bail!("No earlier selections");
+                }
+                view.make_selection_visisble(&buffer, scrolloff);
+                Ok(())
+            },
+        ),
+        Command::new(
+            "selection-redo",
+            "Reapply the last selection change undone by selection-undo",
+            |engine: Engine| {
+                let state = engine.state_mut();
+                let scrolloff = state.active_options().scrolloff;
+                let (mut view, buffer) = view_buffer(state);
+                if !view.redo_selections(&buffer.contents) {
+                    anyhow::bail!("No later selections");
+                }
+                view.make_selection_visisble(&buffer, scrolloff);
+                Ok(())
+            },
+        ),
+        Command::new(
+            "keep-primary-selection",
+            "Drop all selections but the primary, leaving a cursor at its head",
+            keep_primary_selection,
+        ),
+        Command::new(
+            "collapse-selections",
+            "Turn each selection into a cursor at its head",
+            collapse_selections,
+        ),
+        Command::new(
+            "keep-matching",
+            "Keep only the selections matching the given regex",
+            |engine: Engine, pattern: String| filter_selections(engine, pattern, true),
+        ).arg_names(&["regex"]),
+        Command::new(
+            "remove-matching",
+            "Remove the selections matching the given regex",
+            |engine: Engine, pattern: String| filter_selections(engine, pattern, false),
+        ).arg_names(&["regex"]),
+        Command::new(
+            "search-next",
+            "Select the next match of the last search for each selection",
+            |engine: Engine| search_next(engine, true),
         ),
         Command::new(
-            "extend-char-up",
-            "Extend selection one char up",
-            |engine: Engine| {
-                move_char_up(engine);
-            },
+            "search-prev",
+            "Select the previous match of the last search for each selection",
+            |engine: Engine| search_next(engine, false),
         ),
-        Command::new("delete", "Delete selected text", |engine: Engine| {
-            delete(engine);
-        }),
         Command::new(
-            "backspace",
-            "Delete character before selection",
-            |engine: Engine| {
-                backspace(engine);
+            "surround",
+            "Surround each selection with the given pair",
+            surround,
+        ).arg_names(&["pair"]).edits(),
+        Command::new(
+            "select-inside",
+            "Select the contents of the nearest surrounding pair",
+            |engine: Engine, args: Vec<CommandArg>| match char_arg(&engine, "select-inside", args)?
+            {
+                Some(c) => select_pair(engine, c, false),
+                None => Ok(()),
             },
         ),
         Command::new(
-            "insert",
-            "Insert given text before each selection",
-            |engine: Engine, text: String| {
-                insert(engine, text);
+            "select-around",
+            "Select the nearest surrounding pair and its contents",
+            |engine: Engine, args: Vec<CommandArg>| match char_arg(&engine, "select-around", args)?
+            {
+                Some(c) => select_pair(engine, c, true),
+                None => Ok(()),
             },
         ),
+        Command::new(
+            "delete-surround",
+            "Delete the pair surrounding each selection",
+            delete_surround,
+        ).arg_names(&["pair"]).edits(),
         Command::new(
             "goto-start-of-line",
             "Goto start of line",
@@ -604,6 +2616,11 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
                 goto_start_of_line(engine, true);
             },
         ),
+        Command::new(
+            "goto-line-start-smart",
+            "Goto first non-blank char of line, or start of line if already there",
+            |engine: Engine| goto_start_of_line_smart(engine, true),
+        ),
         Command::new("goto-end-of-line", "Goto end of line", |engine: Engine| {
             goto_end_of_line(engine, true);
         }),
@@ -613,6 +2630,107 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
         Command::new("goto-end", "Goto end of file", |engine: Engine| {
             goto_end(engine, true);
         }),
+        Command::new(
+            "goto-percent",
+            "Goto the line at the given percentage of the file",
+            |engine: Engine, percent: i32| goto_percent(engine, percent),
+        ).arg_names(&["percent"]),
+        Command::new(
+            "move-paragraph-forward",
+            "Move to the blank line after the paragraph",
+            |engine: Engine| goto_paragraph(engine, true, true),
+        ),
+        Command::new(
+            "move-paragraph-backward",
+            "Move to the blank line before the paragraph",
+            |engine: Engine| goto_paragraph(engine, false, true),
+        ),
+        Command::new(
+            "extend-paragraph-forward",
+            "Extend to the blank line after the paragraph",
+            |engine: Engine| goto_paragraph(engine, true, false),
+        ),
+        Command::new(
+            "extend-paragraph-backward",
+            "Extend to the blank line before the paragraph",
+            |engine: Engine| goto_paragraph(engine, false, false),
+        ),
+        char_find_command(
+            "find-char",
+            "Move to the next occurrence of a char on the line",
+            false,
+            false,
+            true,
+        ),
+        char_find_command(
+            "till-char",
+            "Move to just before the next occurrence of a char on the line",
+            true,
+            false,
+            true,
+        ),
+        char_find_command(
+            "find-char-backward",
+            "Move to the previous occurrence of a char on the line",
+            false,
+            true,
+            true,
+        ),
+        char_find_command(
+            "till-char-backward",
+            "Move to just after the previous occurrence of a char on the line",
+            true,
+            true,
+            true,
+        ),
+        char_find_command(
+            "extend-find-char",
+            "Extend to the next occurrence of a char on the line",
+            false,
+            false,
+            false,
+        ),
+        char_find_command(
+            "extend-till-char",
+            "Extend to just before the next occurrence of a char on the line",
+            true,
+            false,
+            false,
+        ),
+        char_find_command(
+            "extend-find-char-backward",
+            "Extend to the previous occurrence of a char on the line",
+            false,
+            true,
+            false,
+        ),
+        char_find_command(
+            "extend-till-char-backward",
+            "Extend to just after the previous occurrence of a char on the line",
+            true,
+            true,
+            false,
+        ),
+        Command::new(
+            "repeat-char-find",
+            "Repeat the last char find",
+            |engine: Engine| repeat_char_find(engine, false),
+        ),
+        Command::new(
+            "repeat-char-find-reverse",
+            "Repeat the last char find in the opposite direction",
+            |engine: Engine| repeat_char_find(engine, true),
+        ),
+        Command::new(
+            "goto-next-change",
+            "Goto the next changed hunk",
+            |engine: Engine| goto_change(engine, true),
+        ),
+        Command::new(
+            "goto-prev-change",
+            "Goto the previous changed hunk",
+            |engine: Engine| goto_change(engine, false),
+        ),
         Command::new(
             "extend-start-of-line",
             "Extend selection to start of line",
@@ -620,6 +2738,11 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
                 goto_start_of_line(engine, false);
             },
         ),
+        Command::new(
+            "extend-line-start-smart",
+            "Extend selection to first non-blank char of line, or start of line if already there",
+            |engine: Engine| goto_start_of_line_smart(engine, false),
+        ),
         Command::new(
             "extend-end-of-line",
             "Extend selection to end of line",
@@ -643,31 +2766,89 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
         ),
         Command::new("undo", "Undo", |engine: Engine| {
             undo(engine);
-        }),
+        }).edits(),
         Command::new("redo", "Redo", |engine: Engine| {
             redo(engine);
-        }),
+        }).edits(),
         Command::new(
             "write",
             "Write buffer to disk or to given path",
+            |engine: Engine, args: Vec<CommandArg>| write(engine, args, false),
+        ),
+        Command::new(
+            "write-force",
+            "Write buffer to disk or to given path, even if the file changed on disk",
+            |engine: Engine, args: Vec<CommandArg>| write(engine, args, true),
+        ),
+        Command::new(
+            "set-trim-on-write",
+            "Toggle trimming trailing whitespace on write, or set it to the given value",
             |engine: Engine, args: Vec<CommandArg>| {
-                let path = args.into_iter().next();
-                if let Some(path) = path {
-                    let path: String = path.into();
-                    let (_, mut buffer) = view_buffer(engine.state_mut());
-                    buffer.backing = BufferBacking::File(path.try_into().unwrap());
-                }
-
-                let state = engine.state();
-                let view = state.active_view;
-                let view = state.view(view).unwrap();
-                let buffer = state.buffer(view.buffer).unwrap();
-                buffer.backing.save(&buffer)
+                toggle(&mut engine.state_mut().editor_options.trim_on_write, args)
             },
         ),
+        Command::new(
+            "save-as",
+            "Write buffer to given path and switch to it, keeping the old file",
+            |engine: Engine, path: String| save_as(engine, path, false, false),
+        ).arg_names(&["path"]),
+        Command::new(
+            "save-as-force",
+            "Write buffer to given path and switch to it, keeping the old file and overwriting the new one",
+            |engine: Engine, path: String| save_as(engine, path, false, true),
+        ).arg_names(&["path"]),
+        Command::new(
+            "rename",
+            "Write buffer to given path and switch to it, removing the old file",
+            |engine: Engine, path: String| save_as(engine, path, true, false),
+        ).arg_names(&["path"]),
+        Command::new(
+            "rename-force",
+            "Write buffer to given path and switch to it, removing the old file and overwriting the new one",
+            |engine: Engine, path: String| save_as(engine, path, true, true),
+        ).arg_names(&["path"]),
+        Command::new(
+            "reload-buffer",
+            "Reload the buffer from its file, unless it has unsaved changes",
+            |engine: Engine| reload_buffer(engine, false),
+        ).edits(),
+        Command::new(
+            "reload-buffer-force",
+            "Reload the buffer from its file, discarding unsaved changes",
+            |engine: Engine| reload_buffer(engine, true),
+        ).edits(),
+        Command::new(
+            "recover",
+            "Restore unsaved changes to the buffer's file left behind by a crash",
+            recover,
+        ).edits(),
+        Command::new(
+            "discard-recovery",
+            "Delete the recovery file left behind by a crash",
+            discard_recovery,
+        ),
         Command::new("quit", "Quit Spiral", |engine: Engine| {
-            engine.state_mut().should_quit = true;
+            let mut state = engine.state_mut();
+            let unsaved = state
+                .buffers
+                .values()
+                .find(|b| matches!(b.backing, BufferBacking::File(_)) && b.is_modified());
+            if let Some(buffer) = unsaved {
+                anyhow::bail!(
+                    "{} has unsaved changes; use quit-force to quit anyway",
+                    buffer.name
+                );
+            }
+            state.should_quit = true;
+            Ok(())
         }),
+        Command::new(
+            "quit-force",
+            "Quit Spiral, discarding unsaved changes",
+            |engine: Engine| {
+                engine.state_mut().should_quit = true;
+            },
+        ),
         Command::new(
             "enter-mode",
             "Enter given mode",
@@ -677,18 +2858,105 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
 
                 Ok(())
             },
+        ).arg_names(&["mode"]),
+        Command::new(
+            "mode",
+            "Show the current mode",
+            |engine: Engine| {
+                let mut state = engine.state_mut();
+                let mut mode = state.current_mode.to_string().to_lowercase();
+                if state.current_mode == Mode::Insert && state.overwrite {
+                    mode.push_str(" (overwrite)");
+                }
+                state.message(MessageLevel::Info, format!("Mode: {mode}"));
+            },
+        ),
+        Command::new(
+            "insert-mode",
+            "Enter insert mode before each selection",
+            |engine: Engine| {
+                enter_insert(engine, false);
+            },
+        ),
+        Command::new(
+            "block-insert",
+            "Enter insert mode with a cursor on each selected line, at the selection's column",
+            block_insert,
+        ).edits(),
+        Command::new(
+            "append",
+            "Enter insert mode after each selection",
+            |engine: Engine| {
+                enter_insert(engine, true);
+            },
+        ).edits(),
+        Command::new(
+            "open-below",
+            "Open a new line below each selection and enter insert mode",
+            |engine: Engine| {
+                open_line(engine, true);
+            },
+        ).edits(),
+        Command::new(
+            "open-above",
+            "Open a new line above each selection and enter insert mode",
+            |engine: Engine| {
+                open_line(engine, false);
+            },
+        ).edits(),
+        Command::new(
+            "source",
+            "Run a file of commands, one per line",
+            |engine: Engine, path: String| engine.source(path),
+        ).arg_names(&["path"]),
+        Command::new(
+            "reload-config",
+            "Reload config",
+            |engine: Engine| match engine.reload_config() {
+                Ok(()) => engine
+                    .state_mut()
+                    .message(MessageLevel::Info, "Config reloaded"),
+                Err(e) => {
+                    error!("{e}");
+                    engine
+                        .state_mut()
+                        .message(MessageLevel::Error, e.to_string());
+                }
+            },
+        ),
+        Command::new(
+            "enable-syntax",
+            "Parse and highlight the current buffer, e.g. after opening a large file",
+            |engine: Engine| {
+                let (_, mut buffer) = view_buffer(engine.state_mut());
+                if !buffer.syntax {
+                    buffer.enable_syntax();
+                }
+            },
+        ),
+        Command::new(
+            "messages",
+            "Show all messages in a scratch buffer",
+            |engine: Engine| {
+                let mut state = engine.state_mut();
+                let contents = state
+                    .messages
+                    .iter()
+                    .map(|msg| format!("[{}] {}\n", msg.level, msg.text))
+                    .collect::<String>();
+                let mut buffer = Buffer::create_from_contents("*messages*".into(), contents.into());
+                buffer.language = "text".into();
+                let buffer_id = buffer.id;
+                state.buffers.insert(buffer_id, buffer);
+                let view = state.create_view(buffer_id);
+                state.set_active_view(view);
+            },
         ),
-        Command::new("reload-config", "Reload config", |engine: Engine| {
-            if let Err(e) = engine.reload_config() {
-                error!("{e}");
-                engine.state_mut().error_log.push(e.to_string());
-            }
-        }),
         Command::new("binds", "Show current keybinds", |engine: Engine| {
             let mut state = engine.state_mut();
             let buffer = state.create_buffer();
             let view = state.create_view(buffer);
-            state.active_view = view;
+            state.set_active_view(view);
 
             let mut contents = String::new();
 
@@ -745,7 +3013,7 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
             let mut state = engine.state_mut();
             let buffer = state.create_buffer();
             let view = state.create_view(buffer);
-            state.active_view = view;
+            state.set_active_view(view);
 
             let mut contents = String::new();
 
@@ -755,6 +3023,37 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
             }
             state.buffers.get_mut(&buffer).unwrap().contents = contents.into();
         }),
+        Command::new(
+            "open",
+            "Open a file, optionally at a position given as path:line or path:line:col",
+            |engine: Engine, arg: String| {
+                let (path, position) = parse_path_position(&arg);
+                if !path.is_file() {
+                    anyhow::bail!("{} is not a file", path.display());
+                }
+                engine.open_at(path, position)?;
+                Ok(())
+            },
+        ).arg_names(&["path"]),
+        Command::new(
+            "goto-line",
+            "Move the cursor to the given line, counted from 1",
+            |engine: Engine, line: i32| {
+                let mut state = engine.state_mut();
+                let view = state.active_view;
+                state.set_cursor_position(view, (line.max(1) - 1) as usize, 0);
+            },
+        ).arg_names(&["line"]),
+        Command::new(
+            "select-lines",
+            "Select the lines from first to last, counted from 1",
+            select_lines,
+        ).arg_names(&["first", "last"]),
+        Command::new(
+            "help",
+            "Show a command's description, arguments and key bindings",
+            help,
+        ).arg_names(&["command"]),
         Command::new("show-kill-ring", "Show kill ring", |engine| {
             show_kill_ring(engine);
         }),
@@ -764,24 +3063,130 @@ pub fn builtin_commands() -> impl Iterator<Item = Command> {
             |engine, before: bool| {
                 paste_kill_ring(engine, before);
             },
-        ),
+        ).arg_names(&["before"]).edits(),
         Command::new("copy-kill-ring", "Copy selection to kill ring", |engine| {
             copy_kill_ring(engine);
         }),
+        Command::new(
+            "clear-kill-ring",
+            "Remove all kill ring entries",
+            |engine: Engine| {
+                engine.state_mut().kill_ring.clear();
+            },
+        ),
         Command::new(
             "close-buffer",
             "Closes the current buffer view",
             close_buffer,
         ),
+        Command::new(
+            "alternate-buffer",
+            "Switch back to the previously active view",
+            alternate_buffer,
+        ),
         Command::new("list-buffers", "Lists the open buffers", list_buffers),
+        Command::new(
+            "diff",
+            "Show two buffers side by side with the lines where they differ highlighted",
+            diff,
+        ).arg_names(&["left", "right"]),
+        Command::new(
+            "lock-scroll",
+            "Toggle whether the panes on screen scroll together",
+            lock_scroll,
+        ),
+        Command::new("diff-close", "Stop showing buffers side by side", |engine: Engine| {
+            engine.state_mut().close_diff();
+        }),
         Command::new("tree-sitter-out", "TODO: Add desciption", tree_sitter_out),
         Command::new("tree-sitter-in", "TODO: Add desciption", tree_sitter_in),
         Command::new("tree-sitter-next", "TODO: Add desciption", tree_sitter_next),
         Command::new("tree-sitter-prev", "TODO: Add desciption", tree_sitter_prev),
+        Command::new("fold", "Fold the syntax node under each cursor", fold),
+        Command::new("unfold", "Unfold folds under each cursor", unfold),
+        Command::new(
+            "set-view-read-only",
+            "Toggle whether the current view refuses edits, or set it to the given value",
+            |engine: Engine, args: Vec<CommandArg>| {
+                let mut state = engine.state_mut();
+                let view = state.active_view;
+                toggle(&mut state.views.get_mut(&view).unwrap().read_only, args)
+            },
+        ),
+        Command::new(
+            "set-show-whitespace",
+            "Toggle whitespace rendering, or set it to the given value",
+            |engine: Engine, args: Vec<CommandArg>| {
+                toggle(&mut engine.state_mut().editor_options.show_whitespace, args)
+            },
+        ),
+        Command::new(
+            "set-show-eol",
+            "Toggle end-of-line markers, or set it to the given value",
+            |engine: Engine, args: Vec<CommandArg>| {
+                toggle(&mut engine.state_mut().editor_options.show_eol, args)
+            },
+        ),
+        Command::new(
+            "set-show-eob",
+            "Toggle end-of-buffer markers, or set it to the given value",
+            |engine: Engine, args: Vec<CommandArg>| {
+                toggle(&mut engine.state_mut().editor_options.show_eob, args)
+            },
+        ),
+        Command::new(
+            "select-function",
+            "Select the enclosing function",
+            |engine: Engine| {
+                select_enclosing(engine, "function");
+            },
+        ),
+        Command::new(
+            "select-block",
+            "Select the enclosing block",
+            |engine: Engine| {
+                select_enclosing(engine, "block");
+            },
+        ),
     ]
     .into_iter()
 }
 
+/// Splits a command line into the commands separated by `;`, leaving `;`
+/// inside quoted arguments alone
+pub fn split_command_sequence(line: &str) -> Vec<&str> {
+    let mut commands = vec![];
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut token_start = true;
+    for (i, c) in line.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            ';' => {
+                commands.push(line[start..i].trim());
+                start = i + 1;
+                token_start = true;
+                continue;
+            }
+            '"' if token_start => in_string = true,
+            _ => {}
+        }
+        token_start = c.is_whitespace();
+    }
+    commands.push(line[start..].trim());
+    commands.retain(|cmd| !cmd.is_empty());
+    commands
+}
+
 pub struct CommandArgParser<'a> {
     chars: Peekable<std::str::Chars<'a>>,
 }
@@ -899,8 +3304,8 @@ pub enum CommandArg {
 
 #[derive(Debug, Clone)]
 pub struct CommandArgError {
-    expected: String,
-    found: String,
+    pub expected: String,
+    pub found: String,
 }
 
 impl Display for CommandArgError {
@@ -992,8 +3397,50 @@ impl<'lua> IntoLua<'lua> for CommandArg {
     }
 }
 
+impl<'lua> FromLua<'lua> for CommandArg {
+    fn from_lua(value: mlua::Value<'lua>, _lua: &'lua mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Boolean(b) => Ok(CommandArg::Bool(b)),
+            mlua::Value::Integer(i) => Ok(CommandArg::Integer(i)),
+            mlua::Value::Number(n) if n.fract() == 0.0 => Ok(CommandArg::Integer(n as i32)),
+            mlua::Value::String(s) => Ok(CommandArg::String(s.to_str()?.to_string())),
+            other => Err(mlua::Error::runtime(format!(
+                "expected a string, integer or boolean, found {}",
+                other.type_name()
+            ))),
+        }
+    }
+}
+
 pub trait CommandAction<M> {
     fn apply(&self, engine: Engine, args: Vec<CommandArg>) -> anyhow::Result<()>;
+
+    /// Names of the argument types, or `None` if any arguments are accepted
+    fn arg_types(&self) -> Option<Vec<&'static str>> {
+        None
+    }
+}
+
+/// A type a command can take as an argument
+pub trait ArgType: TryFrom<CommandArg> {
+    /// How the type is named in usage errors and help
+    const NAME: &'static str;
+}
+
+impl ArgType for String {
+    const NAME: &'static str = "string";
+}
+
+impl ArgType for i32 {
+    const NAME: &'static str = "integer";
+}
+
+impl ArgType for bool {
+    const NAME: &'static str = "bool";
+}
+
+impl ArgType for CommandArg {
+    const NAME: &'static str = "value";
 }
 
 impl<F: Fn(Engine, Vec<CommandArg>)> CommandAction<((i8,),)> for F {
@@ -1013,7 +3460,7 @@ macro_rules! _impl_for {
         impl <Func, $($ty),*> CommandAction<($($ty,)*)> for Func
         where
             Func: Fn(Engine $(, $ty)*),
-            $($ty: TryFrom<CommandArg>, <$ty as TryFrom<CommandArg>>::Error: std::error::Error + Send + Sync + 'static,)*
+            $($ty: ArgType, <$ty as TryFrom<CommandArg>>::Error: std::error::Error + Send + Sync + 'static,)*
         {
             fn apply(&self, engine: Engine, args: Vec<CommandArg>) -> anyhow::Result<()> {
                 #[allow(unused_mut)]
@@ -1025,12 +3472,16 @@ macro_rules! _impl_for {
                 );
                 Ok(())
             }
+
+            fn arg_types(&self) -> Option<Vec<&'static str>> {
+                Some(vec![$(<$ty as ArgType>::NAME),*])
+            }
         }
 
         impl <Func, $($ty),*> CommandAction<(i8, ($($ty,)*))> for Func
         where
             Func: Fn(Engine $(, $ty)*) -> anyhow::Result<()>,
-            $($ty: TryFrom<CommandArg>, <$ty as TryFrom<CommandArg>>::Error: std::error::Error + Send + Sync + 'static,)*
+            $($ty: ArgType, <$ty as TryFrom<CommandArg>>::Error: std::error::Error + Send + Sync + 'static,)*
         {
             fn apply(&self, engine: Engine, args: Vec<CommandArg>) -> anyhow::Result<()> {
                 #[allow(unused_mut)]
@@ -1041,6 +3492,10 @@ macro_rules! _impl_for {
                     $(${ignore($ty)} iter.next().unwrap().try_into()?,)*
                 )
             }
+
+            fn arg_types(&self) -> Option<Vec<&'static str>> {
+                Some(vec![$(<$ty as ArgType>::NAME),*])
+            }
         }
     };
 }
@@ -1056,3 +3511,334 @@ macro_rules! impl_for {
 }
 
 impl_for!(A, B, C, D, E, F, G, H, I);
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::engine::Size;
+
+    fn view_of(text: &str) -> (ViewMut<HashMap<ViewId, View>>, Buffer) {
+        let buffer = Buffer::create_from_contents("test".into(), Rope::from_str(text));
+        let view = View::new(
+            buffer.id,
+            Size {
+                width: 80,
+                height: 24,
+            },
+        );
+        let id = view.id;
+        (ViewMut::new(HashMap::from([(id, view)]), id), buffer)
+    }
+
+    fn set_selections(view: &mut View, ranges: &[(usize, usize)]) {
+        view.selections = ranges
+            .iter()
+            .map(|&(start, end)| Selection {
+                start,
+                end,
+                ..Selection::new(view.id)
+            })
+            .collect();
+    }
+
+    fn selections(view: &View) -> Vec<(usize, usize)> {
+        view.selections.iter().map(|s| (s.start, s.end)).collect()
+    }
+
+    /// A headless engine editing `text`, with one cursor at `head`
+    fn engine_with(text: &str, head: usize) -> Engine {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        {
+            let mut state = engine.state_mut();
+            let state = &mut *state;
+            let mut view = ViewMut::new(&mut state.views, state.active_view);
+            let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+            buffer.insert(&mut view, text, 0);
+            set_selections(&mut view, &[(head, head)]);
+        }
+        engine
+    }
+
+    /// The text of the active buffer and the head of its first selection
+    fn text_and_head(engine: &Engine) -> (String, usize) {
+        let state = engine.state();
+        let view = &state.views[&state.active_view];
+        let text = state.buffers[&view.buffer].contents.to_string();
+        (text, view.selections[0].head())
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("spiral-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn save_keeps_selections_and_scroll() {
+        let text = "a  \n".repeat(50);
+        let (mut view, buffer) = view_of(&text);
+        set_selections(&mut view, &[(1, 2), (101, 150)]);
+        view.vscroll = 20;
+        let path = temp_path("plain-save");
+
+        BufferBacking::File(path.clone())
+            .save(&buffer.contents)
+            .unwrap();
+
+        assert_eq!(selections(&view), vec![(1, 2), (101, 150)]);
+        assert_eq!(view.vscroll, 20);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn trimming_clamps_selections() {
+        let (mut view, mut buffer) = view_of("ab  \ncd\t\nef\n");
+        // In the whitespace of the first line, across the second line's, and
+        // on the last line
+        set_selections(&mut view, &[(3, 3), (6, 9), (10, 11)]);
+        view.vscroll = 1;
+
+        trim_trailing_whitespace(&mut view, &mut buffer);
+
+        assert_eq!(buffer.contents.to_string(), "ab\ncd\nef\n");
+        assert_eq!(selections(&view), vec![(2, 2), (4, 6), (7, 8)]);
+        assert_eq!(view.vscroll, 1);
+
+        buffer.undo(&mut view);
+        assert_eq!(buffer.contents.to_string(), "ab  \ncd\t\nef\n");
+    }
+
+    #[test]
+    fn edits_move_the_other_views_of_the_buffer_at_once() {
+        let mut buffer = Buffer::create_from_contents("test".into(), Rope::from_str("abc"));
+        let size = Size {
+            width: 80,
+            height: 24,
+        };
+        let (first, mut second) = (View::new(buffer.id, size), View::new(buffer.id, size));
+        set_selections(&mut second, &[(1, 2)]);
+        let (first_id, second_id) = (first.id, second.id);
+        let mut views = HashMap::from([(first_id, first), (second_id, second)]);
+
+        buffer.insert(&mut ViewMut::new(&mut views, first_id), "xy", 0);
+        assert_eq!(selections(&views[&second_id]), vec![(3, 4)]);
+        buffer.remove(&mut ViewMut::new(&mut views, first_id), 0, 3);
+        assert_eq!(selections(&views[&second_id]), vec![(0, 1)]);
+    }
+
+    /// A fresh directory holding `foo.txt`, opened in a headless engine
+    fn engine_with_dir(name: &str) -> (Engine, PathBuf) {
+        let dir = temp_path(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("foo.txt"), "foo\n").unwrap();
+        let engine = Engine::new_headless(80, 24).unwrap();
+        engine
+            .execute_command(&format!("open \"{}\"", dir.join("foo.txt").display()))
+            .unwrap();
+        (engine, dir)
+    }
+
+    #[test]
+    fn rename_to_another_spelling_keeps_the_file() {
+        let (engine, dir) = engine_with_dir("rename-same");
+        let other = dir.join(".").join("foo.txt");
+
+        engine
+            .execute_command(&format!("rename \"{}\"", other.display()))
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dir.join("foo.txt")).unwrap(),
+            "foo\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn save_as_and_rename_refuse_existing_files() {
+        let (engine, dir) = engine_with_dir("save-as-existing");
+        let target = dir.join("bar.txt");
+        std::fs::write(&target, "bar\n").unwrap();
+
+        for command in ["save-as", "rename"] {
+            let result = engine.execute_command(&format!("{command} \"{}\"", target.display()));
+            assert!(result.is_err());
+        }
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "bar\n");
+        assert!(dir.join("foo.txt").exists());
+
+        engine
+            .execute_command(&format!("rename-force \"{}\"", target.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "foo\n");
+        assert!(!dir.join("foo.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn diff_close_removes_the_diff_views() {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        let start = engine.state().active_view;
+        let buffer = engine.state().views[&start].buffer;
+
+        for _ in 0..2 {
+            engine
+                .execute_command(&format!("diff {0} {0}", buffer.0))
+                .unwrap();
+            assert_eq!(engine.state().buffers[&buffer].view_count, 3);
+            engine.execute_command("diff-close").unwrap();
+        }
+
+        let state = engine.state();
+        assert_eq!(state.views.len(), 1);
+        assert_eq!(state.buffers[&buffer].view_count, 1);
+        assert_eq!(state.active_view, start);
+    }
+
+    #[test]
+    fn second_diff_reuses_the_diff_views() {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        let start = engine.state().active_view;
+        let first = engine.state().views[&start].buffer;
+        let second = engine.state_mut().create_buffer();
+
+        engine
+            .execute_command(&format!("diff {0} {0}", first.0))
+            .unwrap();
+        engine
+            .execute_command(&format!("diff {} {}", first.0, second.0))
+            .unwrap();
+
+        let state = engine.state();
+        assert_eq!(state.views.len(), 3);
+        assert_eq!(state.buffers[&first].view_count, 2);
+        assert_eq!(state.buffers[&second].view_count, 1);
+        let diff = state.diff.as_ref().unwrap();
+        assert_eq!(state.views[&diff.right].buffer, second);
+    }
+
+    #[test]
+    fn transpose_chars_at_the_edges() {
+        let transposed = |text: &str, head: usize| {
+            let engine = engine_with(text, head);
+            transpose_chars(engine.clone());
+            text_and_head(&engine)
+        };
+
+        // Nothing before the cursor at the start of the buffer
+        assert_eq!(transposed("ab", 0), ("ab".to_string(), 0));
+        // At the end of the buffer, the two chars before the cursor are swapped
+        assert_eq!(transposed("ab", 2), ("ba".to_string(), 2));
+        assert_eq!(transposed("ab\ncd", 5), ("ab\ndc".to_string(), 5));
+        // Chars are never swapped across a line break
+        assert_eq!(transposed("ab\ncd", 2), ("ba\ncd".to_string(), 2));
+        assert_eq!(transposed("ab\ncd", 3), ("ab\ncd".to_string(), 3));
+        assert_eq!(transposed("a\nb", 1), ("a\nb".to_string(), 1));
+        // A single char has nothing to be swapped with
+        assert_eq!(transposed("a", 0), ("a".to_string(), 0));
+        assert_eq!(transposed("a", 1), ("a".to_string(), 1));
+    }
+
+    #[test]
+    fn transpose_words_at_the_edges() {
+        let transposed = |text: &str, head: usize| {
+            let engine = engine_with(text, head);
+            transpose_words(engine.clone());
+            text_and_head(&engine)
+        };
+
+        assert_eq!(transposed("foo bar", 0), ("bar foo".to_string(), 7));
+        // The last word has nothing after it to be swapped with
+        assert_eq!(transposed("foo bar", 7), ("foo bar".to_string(), 7));
+        // Words on separate lines are swapped, keeping the line break
+        assert_eq!(transposed("foo\nbar", 3), ("bar\nfoo".to_string(), 7));
+        assert_eq!(transposed("foo\n\n", 0), ("foo\n\n".to_string(), 0));
+        assert_eq!(transposed("a", 0), ("a".to_string(), 0));
+        assert_eq!(transposed("", 0), ("".to_string(), 0));
+    }
+
+    #[test]
+    fn collapsing_merges_only_coinciding_cursors() {
+        let engine = engine_with("abcdef\n", 0);
+        {
+            let mut state = engine.state_mut();
+            let state = &mut *state;
+            let view = state.views.get_mut(&state.active_view).unwrap();
+            // The last two share their head
+            set_selections(view, &[(0, 1), (2, 2), (3, 5), (4, 5)]);
+            view.primary_index = 2;
+        }
+
+        collapse_selections(engine.clone());
+
+        let state = engine.state();
+        let view = &state.views[&state.active_view];
+        assert_eq!(selections(view), vec![(1, 1), (2, 2), (5, 5)]);
+        assert_eq!(view.primary_index, 2);
+    }
+
+    #[test]
+    fn overwrite_backspace_restores_the_replaced_chars() {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        engine.execute_command("insert ab").unwrap();
+        engine.execute_command("goto-start").unwrap();
+        engine.execute_command("set-overwrite true").unwrap();
+        let text = || {
+            let state = engine.state();
+            let view = &state.views[&state.active_view];
+            let head = view.selections[0].head();
+            (state.buffers[&view.buffer].contents.to_string(), head)
+        };
+
+        for c in "xyz".chars() {
+            insert_char(engine.clone(), c);
+        }
+        assert_eq!(text(), ("xyz".to_string(), 3));
+
+        engine.execute_command("backspace").unwrap();
+        assert_eq!(text(), ("xy".to_string(), 2));
+        engine.execute_command("backspace").unwrap();
+        assert_eq!(text(), ("xb".to_string(), 1));
+
+        // After moving, backspace only moves left
+        engine.execute_command("goto-end").unwrap();
+        engine.execute_command("backspace").unwrap();
+        engine.execute_command("backspace").unwrap();
+        assert_eq!(text(), ("xb".to_string(), 0));
+    }
+
+    #[test]
+    fn mode_shows_the_current_mode() {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        let shown = |command: &str| {
+            engine.execute_command(command).unwrap();
+            engine.execute_command("mode").unwrap();
+            engine.state().messages.last().unwrap().text.clone()
+        };
+
+        assert_eq!(shown("enter-mode normal"), "Mode: normal");
+        assert_eq!(shown("enter-mode insert"), "Mode: insert");
+        assert_eq!(shown("set-overwrite true"), "Mode: insert (overwrite)");
+        assert_eq!(shown("enter-mode Window"), "Mode: window");
+    }
+
+    #[test]
+    fn usage_errors_name_the_arguments() {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        let error = |command: &str| engine.execute_command(command).unwrap_err().to_string();
+
+        assert_eq!(
+            error("goto-line"),
+            "goto-line expects 1 argument: line (integer)"
+        );
+        assert_eq!(
+            error("goto-line x"),
+            "goto-line expects 1 argument: line (integer); Expected Integer, found String"
+        );
+        assert_eq!(error("undo 1"), "undo takes no arguments");
+        // set-auto-pairs checks its own arguments, so there is no usage to give
+        assert_eq!(error("set-auto-pairs x"), "Expected Bool, found String");
+    }
+}
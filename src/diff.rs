@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use ratatui::style::Color;
+use ropey::Rope;
+use similar::{DiffTag, TextDiff};
+
+use crate::{
+    buffer::{Buffer, BufferId},
+    view::ViewId,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiffLine {
+    /// Only in the right buffer
+    Added,
+    /// Only in the left buffer
+    Removed,
+    /// Replaced by different lines in the other buffer
+    Changed,
+}
+
+impl DiffLine {
+    pub fn color(self) -> Color {
+        match self {
+            DiffLine::Added => Color::Indexed(22),
+            DiffLine::Removed => Color::Indexed(52),
+            DiffLine::Changed => Color::Indexed(58),
+        }
+    }
+}
+
+/// Two views shown side by side with the lines where their buffers differ
+/// highlighted
+pub struct DiffSplit {
+    pub left: ViewId,
+    pub right: ViewId,
+    /// View that was active before the diff opened, made active again when it
+    /// closes
+    pub previous: ViewId,
+    /// Buffers and their versions the lines were computed for
+    versions: Option<[(BufferId, u64); 2]>,
+    pub left_lines: HashMap<usize, DiffLine>,
+    pub right_lines: HashMap<usize, DiffLine>,
+}
+
+impl DiffSplit {
+    pub fn new(left: ViewId, right: ViewId, previous: ViewId) -> Self {
+        Self {
+            left,
+            right,
+            previous,
+            versions: None,
+            left_lines: HashMap::new(),
+            right_lines: HashMap::new(),
+        }
+    }
+
+    pub fn contains(&self, view: ViewId) -> bool {
+        view == self.left || view == self.right
+    }
+
+    /// Highlighted lines of `view`, if it is one of the two sides
+    pub fn lines(&self, view: ViewId) -> Option<&HashMap<usize, DiffLine>> {
+        if view == self.left {
+            Some(&self.left_lines)
+        } else if view == self.right {
+            Some(&self.right_lines)
+        } else {
+            None
+        }
+    }
+
+    /// Recomputes the differing lines if either buffer changed since last time
+    pub fn update(&mut self, left: &Buffer, right: &Buffer) {
+        let versions = [(left.id, left.version), (right.id, right.version)];
+        if self.versions == Some(versions) {
+            return;
+        }
+        (self.left_lines, self.right_lines) = diff_lines(&left.contents, &right.contents);
+        self.versions = Some(versions);
+    }
+}
+
+/// Lines of `old` and `new` that differ from the other, by line index
+pub fn diff_lines(old: &Rope, new: &Rope) -> (HashMap<usize, DiffLine>, HashMap<usize, DiffLine>) {
+    let old = old.to_string();
+    let new = new.to_string();
+    let diff = TextDiff::from_lines(&old, &new);
+
+    let mut old_lines = HashMap::new();
+    let mut new_lines = HashMap::new();
+    for op in diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        let (old_kind, new_kind) = match tag {
+            DiffTag::Equal => continue,
+            // One of the ranges is empty
+            DiffTag::Delete | DiffTag::Insert => (DiffLine::Removed, DiffLine::Added),
+            DiffTag::Replace => (DiffLine::Changed, DiffLine::Changed),
+        };
+        old_lines.extend(old_range.map(|line| (line, old_kind)));
+        new_lines.extend(new_range.map(|line| (line, new_kind)));
+    }
+    (old_lines, new_lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type SortedLines = Vec<(usize, DiffLine)>;
+
+    fn lines(old: &str, new: &str) -> (SortedLines, SortedLines) {
+        let (old, new) = diff_lines(&Rope::from_str(old), &Rope::from_str(new));
+        let sorted = |lines: HashMap<usize, DiffLine>| {
+            let mut lines = lines.into_iter().collect::<Vec<_>>();
+            lines.sort_by_key(|(line, _)| *line);
+            lines
+        };
+        (sorted(old), sorted(new))
+    }
+
+    #[test]
+    fn equal_texts_have_no_diff_lines() {
+        assert_eq!(lines("a\nb\n", "a\nb\n"), (vec![], vec![]));
+    }
+
+    #[test]
+    fn lines_only_on_one_side_are_added_or_removed() {
+        assert_eq!(
+            lines("a\nb\nc\n", "a\nc\nd\n"),
+            (vec![(1, DiffLine::Removed)], vec![(2, DiffLine::Added)])
+        );
+    }
+
+    #[test]
+    fn replaced_lines_are_changed_on_both_sides() {
+        assert_eq!(
+            lines("a\nb\nc\n", "a\nx\ny\nc\n"),
+            (
+                vec![(1, DiffLine::Changed)],
+                vec![(1, DiffLine::Changed), (2, DiffLine::Changed)]
+            )
+        );
+    }
+}
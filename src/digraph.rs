@@ -0,0 +1,118 @@
+/// Two-char sequences for entering chars that aren't on the keyboard, mostly
+/// following RFC 1345
+const DIGRAPHS: &[(&str, char)] = &[
+    // Accented letters
+    ("a'", 'á'),
+    ("e'", 'é'),
+    ("i'", 'í'),
+    ("o'", 'ó'),
+    ("u'", 'ú'),
+    ("y'", 'ý'),
+    ("A'", 'Á'),
+    ("E'", 'É'),
+    ("I'", 'Í'),
+    ("O'", 'Ó'),
+    ("U'", 'Ú'),
+    ("Y'", 'Ý'),
+    ("a!", 'à'),
+    ("e!", 'è'),
+    ("i!", 'ì'),
+    ("o!", 'ò'),
+    ("u!", 'ù'),
+    ("A!", 'À'),
+    ("E!", 'È'),
+    ("I!", 'Ì'),
+    ("O!", 'Ò'),
+    ("U!", 'Ù'),
+    ("a>", 'â'),
+    ("e>", 'ê'),
+    ("i>", 'î'),
+    ("o>", 'ô'),
+    ("u>", 'û'),
+    ("A>", 'Â'),
+    ("E>", 'Ê'),
+    ("I>", 'Î'),
+    ("O>", 'Ô'),
+    ("U>", 'Û'),
+    ("a:", 'ä'),
+    ("e:", 'ë'),
+    ("i:", 'ï'),
+    ("o:", 'ö'),
+    ("u:", 'ü'),
+    ("y:", 'ÿ'),
+    ("A:", 'Ä'),
+    ("E:", 'Ë'),
+    ("I:", 'Ï'),
+    ("O:", 'Ö'),
+    ("U:", 'Ü'),
+    ("a?", 'ã'),
+    ("o?", 'õ'),
+    ("n?", 'ñ'),
+    ("A?", 'Ã'),
+    ("O?", 'Õ'),
+    ("N?", 'Ñ'),
+    ("c,", 'ç'),
+    ("C,", 'Ç'),
+    ("aa", 'å'),
+    ("AA", 'Å'),
+    ("o/", 'ø'),
+    ("O/", 'Ø'),
+    ("ae", 'æ'),
+    ("AE", 'Æ'),
+    ("ss", 'ß'),
+    // Greek
+    ("a*", 'α'),
+    ("b*", 'β'),
+    ("g*", 'γ'),
+    ("d*", 'δ'),
+    ("e*", 'ε'),
+    ("l*", 'λ'),
+    ("m*", 'μ'),
+    ("p*", 'π'),
+    ("s*", 'σ'),
+    ("w*", 'ω'),
+    ("D*", 'Δ'),
+    ("S*", 'Σ'),
+    ("W*", 'Ω'),
+    // Symbols
+    ("Eu", '€'),
+    ("Pd", '£'),
+    ("Ye", '¥'),
+    ("Ct", '¢'),
+    ("SE", '§'),
+    ("PI", '¶'),
+    ("Co", '©'),
+    ("Rg", '®'),
+    ("DG", '°'),
+    ("+-", '±'),
+    ("*X", '×'),
+    ("-:", '÷'),
+    ("!=", '≠'),
+    ("=<", '≤'),
+    (">=", '≥'),
+    ("?2", '≈'),
+    ("00", '∞'),
+    ("->", '→'),
+    ("<-", '←'),
+    ("-!", '↑'),
+    ("-v", '↓'),
+    ("<<", '«'),
+    (">>", '»'),
+    ("!I", '¡'),
+    ("?I", '¿'),
+    ("-N", '–'),
+    ("-M", '—'),
+    (".M", '·'),
+    ("NS", '\u{a0}'),
+];
+
+/// The char entered by typing `first` and then `second`. Either order works.
+pub fn lookup(first: char, second: char) -> Option<char> {
+    let find = |a: char, b: char| {
+        DIGRAPHS.iter().find_map(|(keys, c)| {
+            let mut keys = keys.chars();
+            (keys.next() == Some(a) && keys.next() == Some(b)).then_some(*c)
+        })
+    };
+    find(first, second).or_else(|| find(second, first))
+}
@@ -7,30 +7,55 @@ use std::{
     path::{Path, PathBuf},
     rc::Rc,
     str::FromStr,
+    time::{Duration, Instant},
 };
 
+use anyhow::Context;
 use log::{error, trace};
 use mlua::UserData;
 use ratatui::{
     crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
-    layout::Constraint,
-    style::{Modifier, Style},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
     widgets::Widget,
     Frame,
 };
 use ropey::Rope;
 use tree_sitter::{InputEdit, Point};
+use unicode_width::UnicodeWidthStr;
 
 use crate::{
-    buffer::{Action, Buffer, BufferBacking, BufferId, HistoryAction},
-    command::{builtin_commands, Command, CommandArgParser},
+    buffer::{
+        detect_language, same_file, Action, Buffer, BufferBacking, BufferId, DiskStamp,
+        HistoryAction,
+    },
+    command::{
+        self, builtin_commands, collapse_cursor, insert_char, set_head_pos, CharFind, Command,
+        CommandArg, CommandArgError, CommandArgParser, Overwritten, SearchCount,
+    },
+    diff::DiffSplit,
     keybind::{Binding, Key, Keybindings},
     kill_ring::KillRing,
+    lua::{BufferRef, ViewRef},
+    message::{Message, MessageLevel},
     mode::Mode,
-    view::{View, ViewId, ViewWidget},
+    options::EditorOptions,
+    process::{Process, ProcessId},
+    selection::Selection,
+    view::{
+        Folds, SelectionColors, SelectionSnapshot, View, ViewId, ViewMut, ViewWidget,
+        WhitespaceChars,
+    },
     Options,
 };
 
+/// Called with the active view and its number of selections after an event
+/// changed them
+pub const SELECTION_CHANGED: &str = "selection-changed";
+
+/// Events Lua functions can be registered for with `Editor.on`
+pub const EVENTS: &[&str] = &[SELECTION_CHANGED];
+
 #[derive(Clone)]
 pub struct Engine {
     pub state: Rc<RefCell<EngineState>>,
@@ -40,9 +65,14 @@ pub struct EngineState {
     pub should_quit: bool,
     pub lua: &'static mlua::Lua,
     pub options: Options,
+    pub editor_options: EditorOptions,
     pub buffers: HashMap<BufferId, Buffer>,
     pub views: HashMap<ViewId, View>,
+    /// Views that were active before the current one, most recent last
+    pub view_history: Vec<ViewId>,
     pub active_view: ViewId,
+    /// Two views shown side by side with their differences highlighted
+    pub diff: Option<DiffSplit>,
 
     pub keybinds: Keybindings,
     pub commands: HashMap<String, Command>,
@@ -50,13 +80,66 @@ pub struct EngineState {
     pub key_queue: Vec<Key>,
 
     pub current_mode: Mode,
+    /// Typed chars replace the char under the cursor instead of being inserted
+    pub overwrite: bool,
+    /// What each char typed in overwrite mode replaced, most recent last
+    pub overwritten: Vec<Overwritten>,
 
     pub cli: CommandLine,
-    pub error_log: Vec<String>,
+    pub messages: Vec<Message>,
+
+    /// Set when the screen needs to be redrawn
+    pub dirty: bool,
+    /// When the screen needs to be redrawn regardless of input, e.g. for a message timeout
+    pub redraw_at: Option<Instant>,
+    /// When files were last checked for outside changes, for `auto_reload`
+    pub last_disk_check: Instant,
 
     pub size: Size,
 
     pub kill_ring: KillRing,
+
+    pub last_search: Option<String>,
+    /// Shown in the command line while the primary selection is on a match
+    /// of the last search
+    pub search_count: Option<SearchCount>,
+
+    pub whitespace_chars: WhitespaceChars,
+    pub selection_colors: SelectionColors,
+
+    /// Bracket and quote pairs as `(open, close)`
+    pub pairs: Vec<(char, char)>,
+
+    /// Status line names and colors of modes, set from Lua
+    pub mode_styles: HashMap<Mode, ModeStyle>,
+
+    /// Command waiting for the next typed char, which is passed as its last
+    /// argument after the ones already given
+    pub char_prompt: Option<(String, Vec<CommandArg>)>,
+    /// Rest of the bound commands after the one that started `char_prompt`,
+    /// run once it has its char
+    pub after_char_prompt: Vec<String>,
+    /// The last `find-char` style motion, for `repeat-char-find`
+    pub last_char_find: Option<CharFind>,
+
+    /// How many `recording_selections` calls are running
+    pub selection_record_depth: usize,
+
+    /// Lua files currently being loaded, innermost last
+    pub lua_files: Vec<PathBuf>,
+
+    /// Child processes started with `Editor.spawn`
+    pub processes: HashMap<ProcessId, Process>,
+
+    /// Lua functions to call when a buffer of the given language is opened
+    pub filetype_hooks: HashMap<String, Vec<mlua::Function<'static>>>,
+    /// Lua functions registered with `Editor.on`, by event name
+    pub event_hooks: HashMap<String, Vec<mlua::Function<'static>>>,
+    /// Active view and its selections as of the last `selection-changed` event
+    pub last_selections: Option<(ViewId, SelectionSnapshot)>,
+
+    /// Tree-sitter node kinds per language and category, e.g. `("rust", "function")`
+    pub node_kinds: HashMap<(String, String), Vec<String>>,
 }
 
 #[derive(Clone, Copy)]
@@ -65,15 +148,52 @@ pub struct Size {
     pub height: usize,
 }
 
+/// Returned by `reload_config` when there is no config file to load
+#[derive(Debug)]
+pub struct NoConfig {
+    pub user_config_path: PathBuf,
+}
+
+impl std::fmt::Display for NoConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No lua config found; create one at {}",
+            self.user_config_path.display()
+        )
+    }
+}
+
+impl std::error::Error for NoConfig {}
+
 impl Engine {
-    pub fn new(options: Options) -> anyhow::Result<Self> {
+    pub fn new(options: Options, size: Size) -> anyhow::Result<Self> {
         let s = Self {
-            state: Rc::new(RefCell::new(EngineState::new(options))),
+            state: Rc::new(RefCell::new(EngineState::new(options, size))),
         };
         crate::lua::init_lua(s.clone())?;
         Ok(s)
     }
 
+    /// Creates an engine that isn't attached to a terminal, for tests and scripts.
+    /// No config is loaded; call `reload_config` if one is wanted.
+    ///
+    /// ```
+    /// use spiral::engine::Engine;
+    ///
+    /// let engine = Engine::new_headless(80, 24).unwrap();
+    /// engine.execute_command("insert hello").unwrap();
+    /// engine.execute_command("goto-start").unwrap();
+    /// engine.execute_command("delete").unwrap();
+    /// ```
+    pub fn new_headless(width: usize, height: usize) -> anyhow::Result<Self> {
+        let options = Options {
+            ignore_global_config: true,
+            ..Options::default()
+        };
+        Self::new(options, Size { width, height })
+    }
+
     pub fn state(&self) -> Ref<EngineState> {
         self.state.borrow()
     }
@@ -83,36 +203,47 @@ impl Engine {
     }
 
     pub fn reload_config(&self) -> anyhow::Result<()> {
-        let mut paths = vec![];
-        if !self.state().options.ignore_global_config {
-            paths.push(PathBuf::from("/etc/spiral/config.lua"));
-        }
-
-        let mut path = dirs::config_dir()
-            .map(|mut p| {
-                p.push("spiral");
-                p
-            })
-            .unwrap_or(PathBuf::from("."));
-        path.push("config.lua");
-        let user_config_path = path.display().to_string();
-        paths.push(path);
-
-        if let Some(path) = self.state().options.config.as_ref() {
-            paths.push(path.into());
-        }
-
-        paths.retain(|p| p.exists());
-
-        if paths.is_empty() {
-            anyhow::bail!("No lua config found; create one at {}", user_config_path);
-        }
+        let paths = if let Some(path) = self.state().options.config.clone() {
+            if !path.exists() {
+                anyhow::bail!("Config file {} does not exist", path.display());
+            }
+            vec![path]
+        } else if self.state().options.ignore_global_config {
+            vec![]
+        } else {
+            let mut path = dirs::config_dir()
+                .map(|mut p| {
+                    p.push("spiral");
+                    p
+                })
+                .unwrap_or(PathBuf::from("."));
+            path.push("config.lua");
+
+            let mut paths = vec![PathBuf::from("/etc/spiral/config.lua"), path.clone()];
+            paths.retain(|p| p.exists());
+            if paths.is_empty() {
+                return Err(NoConfig {
+                    user_config_path: path,
+                }
+                .into());
+            }
+            paths
+        };
 
         self.state_mut().commands = builtin_commands().map(|c| (c.name.clone(), c)).collect();
         self.state_mut().keybinds.binds.clear();
-
-        for path in paths {
-            self.load_lua(&path)?;
+        self.state_mut().filetype_hooks.clear();
+        self.state_mut().event_hooks.clear();
+
+        // Keep loading the remaining files so one broken file doesn't take the rest down
+        let errors = paths
+            .iter()
+            .filter_map(|path| self.load_lua(path).err())
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            self.state_mut().keybinds.bind_fallbacks();
+            anyhow::bail!("{}", errors.join("; "));
         }
 
         Ok(())
@@ -120,10 +251,46 @@ impl Engine {
 
     pub fn load_lua(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
         let path = path.as_ref();
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+        if self.state().lua_files.contains(&canonical) {
+            anyhow::bail!("{} is already being loaded", path.display());
+        }
+
         let lua = self.state.borrow().lua;
-        lua.load(std::fs::read_to_string(path)?)
-            .set_name(path.to_string_lossy())
-            .exec()?;
+        let source = std::fs::read_to_string(path)?;
+        self.state_mut().lua_files.push(canonical);
+        let result = lua.load(source).set_name(path.to_string_lossy()).exec();
+        self.state_mut().lua_files.pop();
+        result.map_err(|e| anyhow::anyhow!(lua_error_summary(&e)))
+    }
+
+    /// Loads a Lua file, resolving relative paths against the directory of the
+    /// file currently being loaded
+    pub fn require_lua(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let path = match self.state().lua_files.last().and_then(|p| p.parent()) {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.to_path_buf(),
+        };
+        self.load_lua(path)
+    }
+
+    /// Runs a file of editor commands, one per line. Blank lines and lines
+    /// starting with `#` are skipped.
+    pub fn source(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let script = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+        for (i, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.execute_command(line)
+                .map_err(|e| anyhow::anyhow!("{}:{}: {e}", path.display(), i + 1))?;
+        }
         Ok(())
     }
 
@@ -139,8 +306,70 @@ impl Engine {
         self.state_mut().create_buffer()
     }
 
-    pub fn open(&self, path: impl AsRef<Path>) -> ViewId {
-        self.state_mut().open(path)
+    pub fn open(&self, path: impl AsRef<Path>) -> anyhow::Result<ViewId> {
+        let view = self.state_mut().open(path)?;
+        let buffer = self.state().views[&view].buffer;
+        // Hooks already ran for buffers that were open before
+        if self.state().buffers[&buffer].view_count == 1 {
+            self.run_filetype_hooks(buffer);
+        }
+        Ok(view)
+    }
+
+    /// Opens `path` with the cursor at a 0-based line and column, if given
+    pub fn open_at(
+        &self,
+        path: impl AsRef<Path>,
+        position: Option<(usize, usize)>,
+    ) -> anyhow::Result<ViewId> {
+        let view = self.open(path)?;
+        if let Some((line, col)) = position {
+            self.state_mut().set_cursor_position(view, line, col);
+        }
+        Ok(view)
+    }
+
+    pub fn open_contents(&self, name: String, rope: Rope) -> ViewId {
+        let view = self.state_mut().open_contents(name, rope);
+        let buffer = self.state().views[&view].buffer;
+        self.run_filetype_hooks(buffer);
+        view
+    }
+
+    /// Registers a Lua function to call on `event`, one of [`EVENTS`]
+    pub fn add_event_hook(
+        &self,
+        event: String,
+        hook: mlua::Function<'static>,
+    ) -> anyhow::Result<()> {
+        if !EVENTS.contains(&event.as_str()) {
+            anyhow::bail!("Unknown event '{event}'");
+        }
+        self.state_mut()
+            .event_hooks
+            .entry(event)
+            .or_default()
+            .push(hook);
+        Ok(())
+    }
+
+    /// Calls the `Editor.on_filetype` hooks registered for the buffer's language
+    pub fn run_filetype_hooks(&self, buffer: BufferId) {
+        let hooks = {
+            let state = self.state();
+            let language = &state.buffers[&buffer].language;
+            state
+                .filetype_hooks
+                .get(language)
+                .cloned()
+                .unwrap_or_default()
+        };
+        for hook in hooks {
+            if let Err(e) = hook.call::<_, ()>(BufferRef { id: buffer }) {
+                self.state_mut()
+                    .message(MessageLevel::Error, format!("{e}"));
+            }
+        }
     }
 
     pub fn get_open_buffers(&self) -> Vec<BufferId> {
@@ -156,15 +385,12 @@ impl Engine {
     }
 
     pub fn event(&self, event: Event) -> anyhow::Result<bool> {
+        // Events are the only thing that change state between draws
+        self.request_redraw();
         match event {
             Event::FocusGained => {}
             Event::FocusLost => {}
-            Event::Key(key) if key.kind != KeyEventKind::Release => match key.code {
-                KeyCode::Char('q') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                    return Ok(true)
-                }
-                _ => self.key_event(key),
-            },
+            Event::Key(key) if key.kind != KeyEventKind::Release => self.key_event(key),
             Event::Mouse(_) => {}
             Event::Paste(_) => {}
             Event::Resize(width, height) => {
@@ -175,10 +401,53 @@ impl Engine {
             }
             _ => {}
         }
+        self.dispatch_selection_changed();
 
         Ok(self.state().should_quit)
     }
 
+    /// Calls the `selection-changed` hooks with the active view and its number
+    /// of selections, if either changed since they were last called
+    fn dispatch_selection_changed(&self) {
+        let current_selections = |state: &EngineState| {
+            state
+                .views
+                .get(&state.active_view)
+                .map(|view| (view.id, view.selection_snapshot()))
+        };
+        let (view, count, hooks) = {
+            let mut state = self.state_mut();
+            let current = current_selections(&state);
+            if current == state.last_selections {
+                return;
+            }
+            let Some((view, snapshot)) = current else {
+                return;
+            };
+            let count = snapshot.selections.len();
+            state.last_selections = Some((view, snapshot));
+            let hooks = state
+                .event_hooks
+                .get(SELECTION_CHANGED)
+                .cloned()
+                .unwrap_or_default();
+            (view, count, hooks)
+        };
+        if hooks.is_empty() {
+            return;
+        }
+
+        for hook in hooks {
+            if let Err(e) = hook.call::<_, ()>((ViewRef { id: view }, count)) {
+                self.state_mut()
+                    .message(MessageLevel::Error, format!("{e}"));
+            }
+        }
+        // Selections changed by the hooks themselves don't call them again
+        let mut state = self.state_mut();
+        state.last_selections = current_selections(&state);
+    }
+
     pub fn key_event(&self, key_ev: KeyEvent) {
         let key = Key {
             code: key_ev.code,
@@ -191,19 +460,54 @@ impl Engine {
                 CommandLineEvent::None => {}
                 CommandLineEvent::Cancel => {
                     state.cli.focus = false;
+                    state.cli.target = PromptTarget::Commands;
                 }
-                CommandLineEvent::Exec(cmd) => {
+                CommandLineEvent::Exec(text) => {
+                    let target = std::mem::take(&mut state.cli.target);
                     drop(state);
-                    if let Err(e) = self.execute_command(&cmd) {
+                    let result = match target {
+                        PromptTarget::Commands => self.execute_command_line(&text),
+                        PromptTarget::Command(cmd) => {
+                            self.run_command(&cmd, vec![CommandArg::String(text)])
+                        }
+                        PromptTarget::Lua(f) => f
+                            .call::<_, ()>(text)
+                            .map_err(|e| anyhow::anyhow!(lua_error_summary(&e))),
+                    };
+                    if let Err(e) = result {
                         error!("{e}");
-                        self.state_mut().error_log.push(format!("{e}"));
+                        self.state_mut()
+                            .message(MessageLevel::Error, format!("{e}"));
                     }
                 }
             }
             return;
         }
 
-        if key.code == KeyCode::Esc && key.modifiers.is_empty() {
+        // Any key other than a plain char cancels the prompt
+        if let Some((cmd, mut args)) = state.char_prompt.take() {
+            let rest = std::mem::take(&mut state.after_char_prompt);
+            drop(state);
+            if let KeyCode::Char(c) = key.code
+                && is_typed_char(&key_ev)
+            {
+                args.push(CommandArg::String(c.to_string()));
+                if let Err(e) = self.run_command(&cmd, args) {
+                    error!("{e}");
+                    self.state_mut()
+                        .message(MessageLevel::Error, format!("{e}"));
+                } else if self.state().char_prompt.is_some() {
+                    // Prompting for another char, like the second of a digraph
+                    self.state_mut().after_char_prompt = rest;
+                } else {
+                    self.run_bound_commands(rest);
+                }
+            }
+            return;
+        }
+
+        // Terminals that send Alt as an Esc prefix report Esc pressed twice as A-esc
+        if key.code == KeyCode::Esc && (key.modifiers - KeyModifiers::ALT).is_empty() {
             if !state.key_queue.is_empty() {
                 state.key_queue.clear();
             } else if !matches!(state.current_mode, Mode::Normal) {
@@ -217,28 +521,17 @@ impl Engine {
         let Some(binding) = state.keybinds.get(&state.current_mode, &keys) else {
             state.key_queue.clear();
 
+            // Unbound chords like A-x are dropped rather than typed
             if matches!(state.current_mode, Mode::Insert)
                 && let KeyCode::Char(c) = key.code
+                && is_typed_char(&key_ev)
             {
-                let state = &mut *state;
-                let view = state.views.get_mut(&state.active_view).unwrap();
-                let buffer = state.buffers.get_mut(&view.buffer).unwrap();
-
-                let mut actions = vec![];
-
-                for i in 0..view.selections.len() {
-                    let s = view.selections[i];
-                    buffer.insert(view, &c.to_string(), s.start);
-                    actions.push(Action::TextInsertion {
-                        text: c.to_string(),
-                        start: s.start,
-                    });
+                if let Err(e) = state.check_editable() {
+                    state.message(MessageLevel::Error, format!("{e}"));
+                    return;
                 }
-
-                buffer.history.register_edit(HistoryAction { actions });
-                buffer.recalc_tree();
-
-                view.make_selection_visisble(buffer);
+                drop(state);
+                insert_char(self.clone(), c);
             }
             return;
         };
@@ -251,69 +544,592 @@ impl Engine {
                 let cmd = cmd.clone();
                 state.key_queue.clear();
                 drop(state);
-                for cmd in cmd {
-                    if let Err(e) = self.execute_command(&cmd) {
-                        error!("{e}");
-                        self.state_mut().error_log.push(format!("{e}"));
-                        break;
-                    }
-                }
+                self.run_bound_commands(cmd);
             }
         }
     }
 
+    /// Runs the commands of a binding in order, each seeing the mode and
+    /// selections left by the ones before. Stops at the first error, and
+    /// holds back the rest while a command waits for a typed char.
+    fn run_bound_commands(&self, commands: Vec<String>) {
+        self.recording_selections(|| {
+            let mut commands = commands.into_iter();
+            while let Some(cmd) = commands.next() {
+                if let Err(e) = self.execute_command(&cmd) {
+                    error!("{e}");
+                    self.state_mut()
+                        .message(MessageLevel::Error, format!("{e}"));
+                    break;
+                }
+                let mut state = self.state_mut();
+                if state.char_prompt.is_some() {
+                    state.after_char_prompt = commands.collect();
+                    break;
+                }
+            }
+        });
+    }
+
     pub fn execute_command(&self, command: &str) -> anyhow::Result<()> {
         let (cmd, args) = command
             .split_once(|c: char| c.is_whitespace())
             .unwrap_or((command, ""));
-        let state = self.state();
         let mut parser = CommandArgParser::new(args);
         let args = parser.args()?;
+        self.run_command(cmd, args)
+    }
 
+    /// Runs a line typed into the command line, which may hold several
+    /// commands separated by `;`. Stops at the first one that fails.
+    pub fn execute_command_line(&self, line: &str) -> anyhow::Result<()> {
+        for command in command::split_command_sequence(line) {
+            let command = match parse_line_range(command) {
+                Some(((first, last), rest)) => {
+                    let first = self.resolve_line_address(first);
+                    let last = self.resolve_line_address(last);
+                    self.run_command(
+                        "select-lines",
+                        vec![CommandArg::Integer(first), CommandArg::Integer(last)],
+                    )?;
+                    rest
+                }
+                None => command,
+            };
+            if !command.is_empty() {
+                self.execute_command(command)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The line number, counted from 1, that `address` refers to in the active view.
+    /// May be past the end of the buffer.
+    fn resolve_line_address(&self, address: LineAddress) -> i32 {
+        match address {
+            LineAddress::Number(n) => n.try_into().unwrap_or(i32::MAX),
+            LineAddress::Current => {
+                let state = self.state();
+                let view = &state.views[&state.active_view];
+                let text = &state.buffers[&view.buffer].contents;
+                let head = view
+                    .primary_selection()
+                    .map_or(0, |s| s.head().min(text.len_chars()));
+                text.char_to_line(head) as i32 + 1
+            }
+            LineAddress::Last => i32::MAX,
+        }
+    }
+
+    /// Runs the command `cmd` with already parsed arguments
+    pub fn run_command(&self, cmd: &str, args: Vec<CommandArg>) -> anyhow::Result<()> {
+        let state = self.state();
         let Some(command) = state.commands.get(cmd) else {
-            anyhow::bail!("Unknown command {cmd}");
+            return Err(command::unknown_command(&state, cmd));
         };
+        command.check_arg_count(&args)?;
+        if command.edits {
+            state.check_editable()?;
+        }
         let action = command.action.clone();
+        let usage = command.usage();
         drop(state);
-        action(self.clone(), args)
+        let result = self.recording_selections(|| action(self.clone(), args));
+        result.map_err(|e| match usage {
+            Some(usage) if e.is::<CommandArgError>() => anyhow::anyhow!("{usage}; {e}"),
+            _ => e,
+        })
+    }
+
+    /// Runs `f`, and if it changed the active view's selections without editing
+    /// the buffer, records the old ones for `selection-undo`. Nested calls are
+    /// recorded as part of the outermost one.
+    fn recording_selections<R>(&self, f: impl FnOnce() -> R) -> R {
+        let before = {
+            let mut state = self.state_mut();
+            state.selection_record_depth += 1;
+            let state = &*state;
+            state
+                .views
+                .get(&state.active_view)
+                .filter(|_| state.selection_record_depth == 1)
+                .map(|view| {
+                    (
+                        view.id,
+                        view.selection_snapshot(),
+                        state.buffers[&view.buffer].version,
+                        view.selection_history.len(),
+                        view.selection_future.len(),
+                    )
+                })
+        };
+
+        let result = f();
+
+        let mut state = self.state_mut();
+        let state = &mut *state;
+        state.selection_record_depth -= 1;
+        if let Some((view_id, snapshot, version, history, future)) = before
+            && let Some(view) = state.views.get_mut(&view_id)
+            && state.buffers[&view.buffer].version == version
+            // selection-undo and selection-redo manage the history themselves
+            && view.selection_history.len() == history
+            && view.selection_future.len() == future
+            && view.selection_snapshot() != snapshot
+        {
+            view.record_selections(snapshot);
+        }
+
+        result
     }
 
     pub fn draw(&self, frame: &mut Frame) {
+        {
+            let mut state = self.state_mut();
+            let state = &mut *state;
+            state.sync_scroll_locks();
+            state.update_diff();
+            state.update_search_count();
+            for view in state.visible_views() {
+                let view = &state.views[&view];
+                let mut end = view.vscroll;
+                for _ in 0..view.size.height {
+                    end = view.folds.next_visible(end);
+                }
+                let buffer = state.buffers.get_mut(&view.buffer).unwrap();
+                buffer.highlight_visible(view.vscroll..end);
+            }
+        }
         self.state().draw(frame);
+
+        let mut state = self.state_mut();
+        state.dirty = false;
+        state.redraw_at = state
+            .visible_message()
+            .and_then(|msg| state.message_expiry(msg));
     }
-}
 
-impl EngineState {
-    pub fn new(options: Options) -> Self {
-        let (width, height) = ratatui::crossterm::terminal::size().unwrap();
-        let size = Size {
-            width: width as usize,
-            height: height as usize,
+    /// Switches to the mode named by the `initial_mode` option
+    pub fn enter_initial_mode(&self) {
+        let mut state = self.state_mut();
+        let name = state.editor_options.initial_mode.clone();
+        let mode = name.parse().unwrap_or(Mode::Normal);
+        // Any name parses as a custom mode, so check that the config uses it
+        let known = !matches!(mode, Mode::Custom(_))
+            || state.keybinds.binds.contains_key(&mode)
+            || state.mode_styles.contains_key(&mode);
+        if known {
+            state.current_mode = mode;
+        } else {
+            state.current_mode = Mode::Normal;
+            state.message(
+                MessageLevel::Error,
+                format!("Config error: unknown initial_mode {name:?}"),
+            );
+        }
+    }
+
+    /// Writes recovery files for all modified file-backed buffers, returning
+    /// the paths that were written
+    pub fn write_recovery_files(&self) -> Vec<PathBuf> {
+        let state = self.state();
+        let mut written = vec![];
+        for buffer in state.buffers.values() {
+            match buffer.write_recovery() {
+                Ok(Some(path)) => written.push(path),
+                Ok(None) => {}
+                Err(e) => error!("Could not write recovery file for {}: {e}", buffer.name),
+            }
+        }
+        written
+    }
+
+    /// Marks the screen as needing to be redrawn
+    pub fn request_redraw(&self) {
+        self.state_mut().dirty = true;
+    }
+
+    /// Whether anything changed since the last draw, or a timed change is due
+    pub fn needs_redraw(&self) -> bool {
+        let state = self.state();
+        state.dirty || state.redraw_at.is_some_and(|t| Instant::now() >= t)
+    }
+
+    /// How long the main loop may wait for input before a timed redraw is due
+    pub fn redraw_timeout(&self) -> Option<Duration> {
+        let state = self.state();
+        let deadline = state
+            .buffers
+            .values()
+            .flat_map(|b| [b.highlight_due(), b.diff_due()])
+            .flatten()
+            .chain(state.redraw_at)
+            .min();
+        let timeout = deadline.map(|t| t.saturating_duration_since(Instant::now()));
+        let poll = if state.buffers.values().any(Buffer::highlight_pending) {
+            Some(HIGHLIGHT_POLL_INTERVAL)
+        } else if !state.processes.is_empty() {
+            Some(PROCESS_POLL_INTERVAL)
+        } else {
+            None
         };
+        match (timeout, poll) {
+            (Some(timeout), Some(poll)) => Some(timeout.min(poll)),
+            (timeout, poll) => timeout.or(poll),
+        }
+    }
 
+    /// Starts or stops highlighting workers to match the options, highlights
+    /// buffers whose edits have settled, and picks up finished results
+    pub fn poll_highlights(&self) {
+        let mut state = self.state_mut();
+        let state = &mut *state;
+        let enabled = state.editor_options.background_highlighting;
+        for buffer in state.buffers.values_mut() {
+            buffer.set_background_highlighting(enabled);
+            if buffer.highlight_due().is_some_and(|t| Instant::now() >= t) {
+                buffer.flush_highlight();
+            }
+            if buffer.poll_highlight() {
+                state.dirty = true;
+            }
+        }
+    }
+
+    /// Hands the output of child processes to their callbacks, which get `nil`
+    /// once a process has closed its output
+    pub fn poll_processes(&self) {
+        let mut outputs = vec![];
+        {
+            let mut state = self.state_mut();
+            state.processes.retain(|_, process| {
+                let (lines, ended) = process.read();
+                if !lines.is_empty() || ended {
+                    outputs.push((process.on_output.clone(), lines, ended));
+                }
+                !ended
+            });
+        }
+
+        for (on_output, lines, ended) in outputs {
+            let result: mlua::Result<()> = try {
+                for line in lines {
+                    on_output.call::<_, ()>(line)?;
+                }
+                if ended {
+                    on_output.call::<_, ()>(mlua::Value::Nil)?;
+                }
+            };
+            let mut state = self.state_mut();
+            if let Err(e) = result {
+                state.message(MessageLevel::Error, format!("{e}"));
+            }
+            state.dirty = true;
+        }
+    }
+
+    /// Recomputes the git signs of buffers whose edits have settled
+    pub fn poll_diffs(&self) {
+        let mut state = self.state_mut();
+        let state = &mut *state;
+        for buffer in state.buffers.values_mut() {
+            if buffer.diff_due().is_some_and(|t| Instant::now() >= t) {
+                buffer.update_diff();
+                state.dirty = true;
+            }
+        }
+    }
+
+    /// Reloads unmodified buffers whose files changed on disk, for buffers with
+    /// `auto_reload` set, and warns once about modified ones
+    pub fn poll_disk_changes(&self) {
+        let changed = {
+            let mut state = self.state_mut();
+            if state.last_disk_check.elapsed() < DISK_CHECK_INTERVAL {
+                return;
+            }
+            state.last_disk_check = Instant::now();
+            state
+                .buffers
+                .values()
+                .filter(|b| state.buffer_options(b.id).auto_reload)
+                .filter_map(|b| {
+                    let BufferBacking::File(path) = &b.backing else {
+                        return None;
+                    };
+                    let current = DiskStamp::read(path)?;
+                    (b.disk_stamp != Some(current) && b.disk_change_warned != Some(current))
+                        .then_some((b.id, current))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut state = self.state_mut();
+        for (id, stamp) in changed {
+            let buffer = state.buffers.get_mut(&id).unwrap();
+            let name = buffer.name.clone();
+            if buffer.is_modified() {
+                buffer.disk_change_warned = Some(stamp);
+                state.message(
+                    MessageLevel::Warn,
+                    format!(
+                        "{name} changed on disk; use reload-buffer-force to discard your changes"
+                    ),
+                );
+            } else if let Err(e) = state.reload(id) {
+                state.message(MessageLevel::Error, format!("Could not reload {name}: {e}"));
+            } else {
+                state.message(MessageLevel::Info, format!("Reloaded {name}"));
+            }
+            state.dirty = true;
+        }
+    }
+}
+
+/// Splits a `path:line` or `path:line:col` argument, as printed by compilers
+/// and grep, into the path and a 0-based position. Existing paths win over
+/// shorter ones, so a file whose name ends in `:` and a number still opens.
+pub fn parse_path_position(arg: &str) -> (PathBuf, Option<(usize, usize)>) {
+    if Path::new(arg).exists() {
+        return (arg.into(), None);
+    }
+    let number = |s: &str| s.parse::<usize>().ok();
+    if let Some((rest, last)) = arg.rsplit_once(':')
+        && let Some(last) = number(last)
+    {
+        if !Path::new(rest).exists()
+            && let Some((path, line)) = rest.rsplit_once(':')
+            && let Some(line) = number(line)
+        {
+            return (
+                path.into(),
+                Some((line.saturating_sub(1), last.saturating_sub(1))),
+            );
+        }
+        return (rest.into(), Some((last.saturating_sub(1), 0)));
+    }
+    (arg.into(), None)
+}
+
+/// A line in a range prefix of the command line
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineAddress {
+    /// Line number counted from 1
+    Number(usize),
+    /// `.`, the line of the primary cursor
+    Current,
+    /// `$`, the last line of the buffer
+    Last,
+}
+
+/// Splits a leading line range such as `10,20`, `.,$` or `5` off a command,
+/// returning the range and the rest of the command
+pub fn parse_line_range(command: &str) -> Option<((LineAddress, LineAddress), &str)> {
+    fn address(s: &str) -> Option<(LineAddress, &str)> {
+        match s.chars().next()? {
+            '.' => Some((LineAddress::Current, &s[1..])),
+            '$' => Some((LineAddress::Last, &s[1..])),
+            c if c.is_ascii_digit() => {
+                let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+                Some((LineAddress::Number(s[..end].parse().ok()?), &s[end..]))
+            }
+            _ => None,
+        }
+    }
+
+    let (first, rest) = address(command)?;
+    let (last, rest) = match rest.strip_prefix(',') {
+        Some(rest) => address(rest)?,
+        None => (first, rest),
+    };
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(((first, last), rest.trim_start()))
+}
+
+/// How often files are checked for outside changes when `auto_reload` is set
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a background highlighting worker is checked for its result
+const HIGHLIGHT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How often child processes are checked for output. Lines printed in between
+/// are handed to the callback together.
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl EngineState {
+    pub fn new(options: Options, size: Size) -> Self {
         let mut state = EngineState {
             should_quit: false,
             lua: Box::leak(Box::new(mlua::Lua::new())),
             options,
+            editor_options: EditorOptions::default(),
             buffers: HashMap::new(),
             active_view: ViewId(usize::MAX),
             views: HashMap::new(),
-            keybinds: Keybindings {
-                binds: HashMap::new(),
-            },
+            view_history: vec![],
+            keybinds: Keybindings::builtin(),
             key_queue: vec![],
             commands: builtin_commands().map(|c| (c.name.clone(), c)).collect(),
             current_mode: Mode::Normal,
+            overwrite: false,
+            overwritten: vec![],
             cli: CommandLine::new(),
-            error_log: vec![],
+            messages: vec![],
+            dirty: true,
+            redraw_at: None,
+            last_disk_check: Instant::now(),
             size,
             kill_ring: KillRing::new(),
+            last_search: None,
+            search_count: None,
+            mode_styles: HashMap::new(),
+            char_prompt: None,
+            after_char_prompt: vec![],
+            last_char_find: None,
+            // `<` and `'` are left out as they are often unpaired, like
+            // comparisons and Rust lifetimes
+            pairs: vec![('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('`', '`')],
+            node_kinds: default_node_kinds(),
+            diff: None,
+            selection_record_depth: 0,
+            lua_files: vec![],
+            processes: HashMap::new(),
+            filetype_hooks: HashMap::new(),
+            event_hooks: HashMap::new(),
+            last_selections: None,
+            whitespace_chars: WhitespaceChars::default(),
+            selection_colors: SelectionColors::default(),
         };
         let buffer = state.create_buffer();
         state.active_view = state.create_view(buffer);
         state
     }
 
+    /// Fails if the active view is read-only
+    pub fn check_editable(&self) -> anyhow::Result<()> {
+        if self.views[&self.active_view].read_only {
+            anyhow::bail!("View is read-only");
+        }
+        Ok(())
+    }
+
+    /// Makes `view` active, remembering the previous one for `alternate-buffer`
+    pub fn set_active_view(&mut self, view: ViewId) {
+        let previous = std::mem::replace(&mut self.active_view, view);
+        if previous == view {
+            return;
+        }
+        self.view_history
+            .retain(|v| *v != previous && *v != view && self.views.contains_key(v));
+        if self.views.contains_key(&previous) {
+            self.view_history.push(previous);
+        }
+    }
+
+    /// Shows `buffer` in `view` instead of its current buffer, closing the old
+    /// buffer if no other view shows it
+    pub fn set_view_buffer(&mut self, view: ViewId, buffer: BufferId) -> anyhow::Result<()> {
+        if !self.buffers.contains_key(&buffer) {
+            anyhow::bail!("No buffer with id {}", buffer.0);
+        }
+        let Some(view) = self.views.get_mut(&view) else {
+            anyhow::bail!("No view with id {}", view.0);
+        };
+        if view.buffer == buffer {
+            return Ok(());
+        }
+        // The old buffer goes away with its last view, so don't take its edits with it
+        let old_buffer = &self.buffers[&view.buffer];
+        if old_buffer.view_count == 1
+            && matches!(old_buffer.backing, BufferBacking::File(_))
+            && old_buffer.is_modified()
+        {
+            anyhow::bail!("{} has unsaved changes", old_buffer.name);
+        }
+        let old = std::mem::replace(&mut view.buffer, buffer);
+        // Nothing of the view's state carries over to a different text
+        view.selections = vec![Selection::new(view.id)];
+        view.primary_index = 0;
+        view.selection_history.clear();
+        view.selection_future.clear();
+        view.folds = Folds::default();
+        view.vscroll = 0;
+        view.hscroll = 0;
+
+        self.buffers.get_mut(&buffer).unwrap().view_count += 1;
+        let old_buffer = self.buffers.get_mut(&old).unwrap();
+        old_buffer.view_count -= 1;
+        if old_buffer.view_count == 0 {
+            self.buffers.remove(&old);
+        }
+        Ok(())
+    }
+
+    /// Closes `view`, and its buffer if no other view shows it
+    pub fn remove_view(&mut self, view: ViewId) {
+        let Some(view) = self.views.remove(&view) else {
+            return;
+        };
+        if let Some(buffer) = self.buffers.get_mut(&view.buffer) {
+            buffer.view_count -= 1;
+            if buffer.view_count == 0 {
+                self.buffers.remove(&view.buffer);
+            }
+        }
+    }
+
+    /// Makes the most recently active view that is still open active, or a new
+    /// scratch buffer if no view is left
+    pub fn activate_remaining_view(&mut self) {
+        let next = match self.alternate_view().or(self.views.keys().next().copied()) {
+            Some(id) => id,
+            None => {
+                let buffer = self.create_buffer();
+                self.create_view(buffer)
+            }
+        };
+        self.set_active_view(next);
+    }
+
+    /// Stops showing buffers side by side and closes the views of the diff,
+    /// going back to the view that was active before it opened
+    pub fn close_diff(&mut self) {
+        let Some(diff) = self.diff.take() else {
+            return;
+        };
+        let was_active = diff.contains(self.active_view);
+        self.remove_view(diff.left);
+        self.remove_view(diff.right);
+        if was_active {
+            if self.views.contains_key(&diff.previous) {
+                self.set_active_view(diff.previous);
+            } else {
+                self.activate_remaining_view();
+            }
+        }
+        self.layout_views();
+    }
+
+    /// The most recently active view other than the current one that is still open
+    pub fn alternate_view(&self) -> Option<ViewId> {
+        self.view_history
+            .iter()
+            .rev()
+            .copied()
+            .find(|v| *v != self.active_view && self.views.contains_key(v))
+    }
+
+    /// Returns the `(open, close)` pair containing `c`, or `(c, c)` if it isn't a known pair
+    pub fn pair_for(&self, c: char) -> (char, char) {
+        self.pairs
+            .iter()
+            .copied()
+            .find(|(open, close)| *open == c || *close == c)
+            .unwrap_or((c, c))
+    }
+
     pub fn create_view(&mut self, buffer: BufferId) -> ViewId {
         self.buffers.get_mut(&buffer).unwrap().view_count += 1;
         let size = Size {
@@ -337,16 +1153,143 @@ impl EngineState {
         buffer_id
     }
 
-    pub fn open(&mut self, path: impl AsRef<Path>) -> ViewId {
+    pub fn open(&mut self, path: impl AsRef<Path>) -> anyhow::Result<ViewId> {
         let path = path.as_ref();
-        let rope = ropey::Rope::from_reader(File::open(path).unwrap()).unwrap();
-        let mut buffer = Buffer::create_from_contents(path.to_string_lossy().to_string(), rope);
+
+        // A file that is already open gets another view onto the same buffer
+        let existing = self
+            .buffers
+            .values()
+            .find(|b| matches!(&b.backing, BufferBacking::File(p) if same_file(p, path)));
+        if let Some(buffer) = existing.map(|b| b.id) {
+            let view = self.create_view(buffer);
+            self.set_active_view(view);
+            return Ok(view);
+        }
+
+        let rope = File::open(path)
+            .and_then(ropey::Rope::from_reader)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+        let view = self.open_contents(path.to_string_lossy().to_string(), rope);
+
+        let buffer = self.views[&view].buffer;
+        let buffer = self.buffers.get_mut(&buffer).unwrap();
         buffer.set_backing(BufferBacking::File(path.to_path_buf()));
+        buffer.language = detect_language(path).unwrap_or("text").into();
+        buffer.disk_stamp = DiskStamp::read(path);
+        buffer.reload_diff_base();
+        if buffer.recovery_file().is_some() {
+            self.message(
+                MessageLevel::Warn,
+                format!(
+                    "{} has unsaved changes from a crash; use recover to restore them or discard-recovery to delete them",
+                    path.display()
+                ),
+            );
+        }
+        Ok(view)
+    }
+
+    /// Replaces the contents of a file-backed buffer with its file as one
+    /// undoable edit. Selections and scroll stay where they were, as far as the
+    /// new text allows.
+    pub fn reload(&mut self, id: BufferId) -> anyhow::Result<()> {
+        let buffer = self.buffers.get_mut(&id).context("No such buffer")?;
+        let BufferBacking::File(path) = &buffer.backing else {
+            anyhow::bail!("{} has no file", buffer.name);
+        };
+        let path = path.clone();
+        let text = std::fs::read_to_string(&path)?;
+
+        let kept = self
+            .views
+            .values()
+            .filter(|v| v.buffer == id)
+            .map(|v| (v.id, v.selections.clone(), v.vscroll))
+            .collect::<Vec<_>>();
+        let (view, _, _) = kept.first().context("Buffer has no view")?;
+        let mut view = ViewMut::new(&mut self.views, *view);
+
+        let len = buffer.contents.len_chars();
+        let deleted_text = buffer.contents.to_string();
+        buffer.remove(&mut view, 0, len);
+        buffer.insert(&mut view, &text, 0);
+        buffer.history.register_edit(HistoryAction {
+            actions: vec![
+                Action::TextDeletion {
+                    deleted_text,
+                    start: 0,
+                    len,
+                },
+                Action::TextInsertion { text, start: 0 },
+            ],
+        });
+        buffer.recalc_tree();
+        buffer.saved_version = buffer.version;
+        buffer.disk_stamp = DiskStamp::read(&path);
+        buffer.disk_change_warned = None;
+        buffer.reload_diff_base();
+
+        let len = buffer.contents.len_chars();
+        let lines = buffer.contents.len_lines();
+        let scrolloff = self
+            .editor_options
+            .with_overrides(&buffer.options)
+            .scrolloff;
+        for (view, selections, vscroll) in kept {
+            let view = self.views.get_mut(&view).unwrap();
+            view.selections = selections
+                .into_iter()
+                .map(|mut s| {
+                    s.start = s.start.min(len);
+                    s.end = s.end.min(len);
+                    s
+                })
+                .collect();
+            view.merge_overlapping_selections();
+            view.vscroll = vscroll.min(lines.saturating_sub(1));
+            view.make_selection_visisble(buffer, scrolloff);
+        }
+        Ok(())
+    }
+
+    /// Collapses the selections of `view` to a cursor at the 0-based `line`
+    /// and `col`, clamped to the buffer
+    pub fn set_cursor_position(&mut self, view: ViewId, line: usize, col: usize) {
+        let Some(view) = self.views.get_mut(&view) else {
+            return;
+        };
+        let Some(buffer) = self.buffers.get(&view.buffer) else {
+            return;
+        };
+        let mut selection = Selection::new(view.id);
+        set_head_pos(&mut selection, buffer, line, col);
+        collapse_cursor(&mut selection);
+        view.selections = vec![selection];
+        view.primary_index = 0;
+        let scrolloff = self
+            .editor_options
+            .with_overrides(&buffer.options)
+            .scrolloff;
+        view.make_selection_visisble(buffer, scrolloff);
+    }
+
+    /// Opens a new buffer without file backing holding `rope`, and makes a view of it active
+    pub fn open_contents(&mut self, name: String, rope: Rope) -> ViewId {
+        let buffer = if rope.len_bytes() > self.editor_options.large_file_bytes {
+            self.message(
+                MessageLevel::Warn,
+                format!("{name} is large; syntax highlighting is off until enable-syntax"),
+            );
+            Buffer::create_without_syntax(name, rope)
+        } else {
+            Buffer::create_from_contents(name, rope)
+        };
         let buffer_id = buffer.id;
         self.buffers.insert(buffer_id, buffer);
 
         let view = self.create_view(buffer_id);
-        self.active_view = view;
+        self.set_active_view(view);
         view
     }
 
@@ -354,54 +1297,318 @@ impl EngineState {
         self.active_view
     }
 
+    pub fn message(&mut self, level: MessageLevel, text: impl Into<String>) {
+        self.messages.push(Message::new(level, text));
+    }
+
+    /// The newest message, unless it has timed out
+    pub fn visible_message(&self) -> Option<&Message> {
+        self.messages
+            .last()
+            .filter(|msg| self.message_expiry(msg).is_none_or(|t| Instant::now() < t))
+    }
+
+    fn message_expiry(&self, msg: &Message) -> Option<Instant> {
+        let timeout = self.editor_options.message_timeout;
+        (timeout != 0).then(|| msg.time + Duration::from_secs(timeout as u64))
+    }
+
+    /// Options in effect for a buffer: its own overrides, then the global options
+    pub fn buffer_options(&self, buffer: BufferId) -> EditorOptions {
+        match self.buffers.get(&buffer) {
+            Some(buffer) => self.editor_options.with_overrides(&buffer.options),
+            None => self.editor_options.clone(),
+        }
+    }
+
+    /// Options in effect for the buffer in the active view
+    pub fn active_options(&self) -> EditorOptions {
+        self.buffer_options(self.views[&self.active_view].buffer)
+    }
+
     pub fn view(&self, id: ViewId) -> Option<&View> {
         self.views.get(&id)
     }
 
     pub fn resize(&mut self, size: Size) {
-        let view_size = Size {
-            width: size.width,
-            height: size.height.saturating_sub(2),
+        self.size = size;
+        self.layout_views();
+        self.dirty = true;
+    }
+
+    /// Area the views are drawn in, above the status and command lines
+    fn view_area(&self) -> Rect {
+        let clamp = |n: usize| n.min(u16::MAX as usize) as u16;
+        Rect::new(
+            0,
+            0,
+            clamp(self.size.width),
+            clamp(self.size.height.saturating_sub(2)),
+        )
+    }
+
+    /// Sizes each view to the pane it is shown in
+    pub fn layout_views(&mut self) {
+        let area = self.view_area();
+        let panes = match &self.diff {
+            Some(diff) => split_panes(&[diff.left, diff.right], area),
+            None => vec![],
         };
         for view in self.views.values_mut() {
-            view.resize(view_size);
-            view.make_selection_visisble(self.buffers.get(&view.buffer).unwrap());
+            let rect = panes
+                .iter()
+                .find(|(id, _)| *id == view.id)
+                .map_or(area, |(_, rect)| *rect);
+            view.resize(Size {
+                width: rect.width as usize,
+                height: rect.height as usize,
+            });
+            if let Some(buffer) = self.buffers.get(&view.buffer) {
+                let scrolloff = self
+                    .editor_options
+                    .with_overrides(&buffer.options)
+                    .scrolloff;
+                view.make_selection_visisble(buffer, scrolloff);
+            }
         }
     }
 
-    pub fn draw(&self, frame: &mut Frame) {
-        let view = self.view(self.active_view).unwrap();
-        let buffer = self.buffer(view.buffer).unwrap();
-        let widget = ViewWidget {
-            view,
-            buffer,
-            mode: &self.current_mode,
+    /// Scrolls the views locked together with one that was scrolled since the
+    /// last sync, preferring the active view when several were
+    pub fn sync_scroll_locks(&mut self) {
+        let mut groups = self
+            .views
+            .values()
+            .filter_map(|view| view.scroll_lock_group)
+            .collect::<Vec<_>>();
+        groups.sort();
+        groups.dedup();
+        for group in groups {
+            let scrolled = |view: &&View| {
+                view.scroll_lock_group == Some(group) && view.vscroll != view.locked_vscroll
+            };
+            let source = self
+                .views
+                .get(&self.active_view)
+                .filter(scrolled)
+                .or_else(|| self.views.values().find(scrolled))
+                .map(|view| view.id);
+            if let Some(source) = source {
+                self.scroll_group_to(source);
+            }
+        }
+    }
+
+    /// Scrolls the views locked together with `source` to the same line,
+    /// clamped to the length of their buffers
+    pub fn scroll_group_to(&mut self, source: ViewId) {
+        let Some(source) = self.views.get_mut(&source) else {
+            return;
         };
-        let status_line = StatusLineWidget {
-            mode: &self.current_mode,
+        source.locked_vscroll = source.vscroll;
+        let (Some(group), vscroll) = (source.scroll_lock_group, source.vscroll) else {
+            return;
         };
-        let cmd_line = CommandLineWidget {
-            command_line: &self.cli,
-            error_log: &self.error_log,
+        for view in self.views.values_mut() {
+            if view.scroll_lock_group != Some(group) {
+                continue;
+            }
+            if let Some(buffer) = self.buffers.get(&view.buffer) {
+                let last_line = buffer.contents.len_lines().saturating_sub(1);
+                view.vscroll = view.folds.visible_start(vscroll.min(last_line));
+            }
+            view.locked_vscroll = view.vscroll;
+        }
+    }
+
+    /// Views on screen: both sides of the diff while one of them is active,
+    /// otherwise just the active view
+    pub fn visible_views(&self) -> Vec<ViewId> {
+        match &self.diff {
+            Some(diff) if diff.contains(self.active_view) => vec![diff.left, diff.right],
+            _ => vec![self.active_view],
+        }
+    }
+
+    /// Recounts the search matches once the text or primary selection changed,
+    /// dropping the count if the selection is no longer on a match
+    fn update_search_count(&mut self) {
+        if let Some(count) = &self.search_count
+            && !count.is_current(self)
+        {
+            self.search_count = SearchCount::count(self);
+        }
+    }
+
+    /// Recomputes the diff highlighting, or closes the diff if one of its
+    /// views was closed
+    fn update_diff(&mut self) {
+        let Some(diff) = &mut self.diff else {
+            return;
         };
+        match (self.views.get(&diff.left), self.views.get(&diff.right)) {
+            (Some(left), Some(right)) => {
+                diff.update(&self.buffers[&left.buffer], &self.buffers[&right.buffer])
+            }
+            _ => self.close_diff(),
+        }
+    }
+
+    /// Keys typed so far of an unfinished binding, or the command waiting for a
+    /// char, shown in the status line
+    fn pending_input(&self) -> Option<String> {
+        if let Some((cmd, _)) = &self.char_prompt {
+            Some(format!("{cmd}…"))
+        } else if !self.key_queue.is_empty() {
+            let keys = self
+                .key_queue
+                .iter()
+                .map(Key::to_string)
+                .collect::<Vec<_>>();
+            Some(format!("{}…", keys.join(" ")))
+        } else {
+            None
+        }
+    }
 
-        let layout = ratatui::layout::Layout::vertical([
+    pub fn draw(&self, frame: &mut Frame) {
+        let layout = Layout::vertical([
             Constraint::Min(0),
             Constraint::Length(1),
             Constraint::Length(1),
         ])
         .split(frame.area());
 
-        frame.render_widget(widget, layout[0]);
+        let panes = split_panes(&self.visible_views(), layout[0]);
+        for (view, area) in &panes {
+            let view = self.view(*view).unwrap();
+            let options = self.buffer_options(view.buffer);
+            let widget = ViewWidget {
+                view,
+                buffer: self.buffer(view.buffer).unwrap(),
+                mode: &self.current_mode,
+                options: &options,
+                whitespace_chars: &self.whitespace_chars,
+                colors: &self.selection_colors,
+                focused: view.id == self.active_view,
+                diff: self.diff.as_ref().and_then(|diff| diff.lines(view.id)),
+            };
+            frame.render_widget(widget, *area);
+        }
+        for pair in panes.windows(2) {
+            let x = pair[0].1.right();
+            for y in layout[0].top()..layout[0].bottom() {
+                frame.buffer_mut()[(x, y)]
+                    .set_char('│')
+                    .set_fg(Color::DarkGray);
+            }
+        }
+
+        let view = self.view(self.active_view).unwrap();
+        let buffer = self.buffer(view.buffer).unwrap();
+        let position = view.primary_selection().map(|s| {
+            let text = &buffer.contents;
+            let head = s.head().min(text.len_chars());
+            let line = text.char_to_line(head);
+            (line, head - text.line_to_char(line))
+        });
+        let status_line = StatusLineWidget {
+            mode: &self.current_mode,
+            style: self.mode_styles.get(&self.current_mode),
+            mode_width: self.editor_options.status_mode_width,
+            overwrite: self.overwrite,
+            name: &buffer.name,
+            read_only: view.read_only,
+            position,
+            pending: self.pending_input(),
+        };
+        let cmd_line = CommandLineWidget {
+            command_line: &self.cli,
+            message: self.visible_message(),
+            search_count: self.search_count.as_ref(),
+        };
+
         frame.render_widget(status_line, layout[1]);
         frame.render_widget(cmd_line, layout[2]);
     }
 }
 
+/// Splits `area` into side by side panes for `views`, with a column between
+/// each for the separator
+fn split_panes(views: &[ViewId], area: Rect) -> Vec<(ViewId, Rect)> {
+    let constraints = views
+        .iter()
+        .map(|_| Constraint::Fill(1))
+        .intersperse(Constraint::Length(1));
+    let areas = Layout::horizontal(constraints).split(area);
+    views
+        .iter()
+        .copied()
+        .zip(areas.iter().step_by(2).copied())
+        .collect()
+}
+
+/// Whether `key` types its char, as opposed to being a chord like `A-x` or `C-w`
+fn is_typed_char(key: &KeyEvent) -> bool {
+    matches!(key.code, KeyCode::Char(_)) && (key.modifiers - KeyModifiers::SHIFT).is_empty()
+}
+
+/// Condenses a Lua error to a single `file:line: message` line, taking the
+/// location from the traceback when the message itself has none
+fn lua_error_summary(e: &mlua::Error) -> String {
+    let location = regex::Regex::new(r#"\[string "([^"]*)"\]:(\d+):"#).unwrap();
+    let text = e.to_string();
+    let mut lines = text.lines();
+    let message = lines.next().unwrap_or_default();
+    if location.is_match(message) {
+        return location.replace(message, "$1:$2:").into_owned();
+    }
+    match lines.find_map(|l| location.captures(l)) {
+        Some(caps) => format!("{}:{}: {message}", &caps[1], &caps[2]),
+        None => message.to_string(),
+    }
+}
+
+fn default_node_kinds() -> HashMap<(String, String), Vec<String>> {
+    [
+        (
+            "rust",
+            "function",
+            &["function_item", "closure_expression"][..],
+        ),
+        ("rust", "block", &["block"][..]),
+    ]
+    .into_iter()
+    .map(|(lang, category, kinds)| {
+        (
+            (lang.to_string(), category.to_string()),
+            kinds.iter().map(|k| k.to_string()).collect(),
+        )
+    })
+    .collect()
+}
+
 pub struct CommandLine {
     pub focus: bool,
     pub contents: String,
+    /// Char index into `contents`
     pub cursor: usize,
+    /// Shown before the contents, e.g. `:` for commands or `/` for searches
+    pub prompt: String,
+    /// What the contents are passed to when entered
+    pub target: PromptTarget,
+}
+
+/// What entering the command line does with its contents
+#[derive(Default)]
+pub enum PromptTarget {
+    /// Runs them as a command line
+    #[default]
+    Commands,
+    /// Runs the named command with them as its argument
+    Command(String),
+    /// Calls a Lua function with them, for `Editor.prompt`
+    Lua(mlua::Function<'static>),
 }
 
 impl CommandLine {
@@ -410,13 +1617,34 @@ impl CommandLine {
             focus: false,
             contents: String::new(),
             cursor: 0,
+            prompt: ":".into(),
+            target: PromptTarget::Commands,
         }
     }
 
+    /// Focuses the command line, showing `prompt` before what is typed
+    pub fn open(&mut self, prompt: impl Into<String>, target: PromptTarget) {
+        self.focus = true;
+        self.prompt = prompt.into();
+        self.target = target;
+    }
+
+    fn len_chars(&self) -> usize {
+        self.contents.chars().count()
+    }
+
+    /// Byte index in `contents` of the char at `index`
+    fn byte_index(&self, index: usize) -> usize {
+        self.contents
+            .char_indices()
+            .nth(index)
+            .map_or(self.contents.len(), |(i, _)| i)
+    }
+
     pub fn key_event(&mut self, key: KeyEvent) -> CommandLineEvent {
         match key.code {
             KeyCode::Backspace if self.cursor > 0 => {
-                self.contents.remove(self.cursor - 1);
+                self.contents.remove(self.byte_index(self.cursor - 1));
                 self.cursor -= 1;
             }
             KeyCode::Enter => {
@@ -432,7 +1660,7 @@ impl CommandLine {
             KeyCode::Left if self.cursor > 0 => {
                 self.cursor -= 1;
             }
-            KeyCode::Right if self.cursor < self.contents.len() => {
+            KeyCode::Right if self.cursor < self.len_chars() => {
                 self.cursor += 1;
             }
             KeyCode::Up => {}
@@ -440,16 +1668,16 @@ impl CommandLine {
             KeyCode::Home if self.cursor > 0 => {
                 self.cursor = 0;
             }
-            KeyCode::End if self.cursor < self.contents.len() => {
-                self.cursor = self.contents.len();
+            KeyCode::End if self.cursor < self.len_chars() => {
+                self.cursor = self.len_chars();
             }
             KeyCode::Tab => {}
             KeyCode::BackTab => {}
-            KeyCode::Delete if self.cursor < self.contents.len() => {
-                self.contents.remove(self.cursor);
+            KeyCode::Delete if self.cursor < self.len_chars() => {
+                self.contents.remove(self.byte_index(self.cursor));
             }
-            KeyCode::Char(c) => {
-                self.contents.insert(self.cursor, c);
+            KeyCode::Char(c) if is_typed_char(&key) => {
+                self.contents.insert(self.byte_index(self.cursor), c);
                 self.cursor += 1;
             }
             KeyCode::Esc => {
@@ -472,7 +1700,10 @@ pub enum CommandLineEvent {
 
 pub struct CommandLineWidget<'a> {
     pub command_line: &'a CommandLine,
-    pub error_log: &'a [String],
+    /// Message to show when the command line isn't focused
+    pub message: Option<&'a Message>,
+    /// Shown when there is no message
+    pub search_count: Option<&'a SearchCount>,
 }
 
 impl<'a> Widget for CommandLineWidget<'a> {
@@ -481,29 +1712,63 @@ impl<'a> Widget for CommandLineWidget<'a> {
         Self: Sized,
     {
         if self.command_line.focus {
-            buf[(area.x, area.y)].set_char(':');
-            buf.set_string(
-                area.x + 1,
+            let cli = self.command_line;
+            let (x, _) = buf.set_stringn(
+                area.x,
                 area.y,
-                &self.command_line.contents,
+                &cli.prompt,
+                area.width as usize,
                 Style::new(),
             );
-            buf[(area.x + 1 + self.command_line.cursor as u16, area.y)]
-                .modifier
-                .insert(Modifier::REVERSED);
-        } else if let Some(err) = self.error_log.last() {
+            buf.set_string(x, area.y, &cli.contents, Style::new());
+            let before_cursor = cli.contents.chars().take(cli.cursor).collect::<String>();
+            let cursor_x = x as usize + before_cursor.width();
+            if cursor_x < area.right() as usize {
+                buf[(cursor_x as u16, area.y)]
+                    .modifier
+                    .insert(Modifier::REVERSED);
+            }
+        } else if let Some(msg) = self.message {
+            buf.set_string(
+                area.x,
+                area.y,
+                &msg.text,
+                Style::new().fg(msg.level.color()),
+            );
+        } else if let Some(count) = self.search_count {
             buf.set_string(
                 area.x,
                 area.y,
-                err,
-                Style::new().fg(ratatui::style::Color::Red),
+                format!("match {} of {}", count.index, count.total),
+                Style::new().fg(Color::DarkGray),
             );
         }
     }
 }
 
+/// How a mode is shown in the status line
+#[derive(Default)]
+pub struct ModeStyle {
+    /// Shown instead of the mode's own name
+    pub name: Option<String>,
+    /// Background of the mode name
+    pub color: Option<Color>,
+}
+
+/// The mode on the left, then the buffer name, and the primary cursor's
+/// position on the right
 pub struct StatusLineWidget<'a> {
     pub mode: &'a Mode,
+    pub style: Option<&'a ModeStyle>,
+    /// Most columns for the mode name, or 0 for no limit
+    pub mode_width: usize,
+    pub overwrite: bool,
+    pub name: &'a str,
+    pub read_only: bool,
+    /// Line and column of the primary cursor, 0-based
+    pub position: Option<(usize, usize)>,
+    /// Input that is waiting for more keys, shown left of the position
+    pub pending: Option<String>,
 }
 
 impl<'a> Widget for StatusLineWidget<'a> {
@@ -512,6 +1777,220 @@ impl<'a> Widget for StatusLineWidget<'a> {
         Self: Sized,
     {
         buf.set_style(area, Style::new().bg(ratatui::style::Color::DarkGray));
-        buf.set_stringn(area.x, area.y, self.mode.to_string(), 8, Style::new());
+        let width = area.width as usize;
+
+        let name = self.style.and_then(|s| s.name.clone());
+        let mode = match self.mode {
+            Mode::Insert if self.overwrite => "OVERWRITE".to_string(),
+            mode => name.unwrap_or_else(|| mode.to_string()),
+        };
+        let style = match self.style.and_then(|s| s.color) {
+            Some(color) => Style::new().fg(Color::Black).bg(color),
+            None => Style::new(),
+        };
+        let mode_width = match self.mode_width {
+            0 => width,
+            max => max.min(width),
+        };
+        let (x, _) = buf.set_stringn(area.x, area.y, mode, mode_width, style);
+
+        let mut position = self
+            .position
+            .map(|(line, col)| format!("{}:{} ", line + 1, col + 1))
+            .unwrap_or_default();
+        if let Some(pending) = &self.pending {
+            position = format!("{pending} {position}");
+        }
+        let position_x = width.saturating_sub(position.chars().count());
+
+        // The name gets what is left between the two, losing its start if too long
+        let name_x = (x - area.x) as usize + 1;
+        let room = position_x.saturating_sub(name_x + 1);
+        let full_name = if self.read_only {
+            format!("{} [RO]", self.name)
+        } else {
+            self.name.to_string()
+        };
+        let len = full_name.chars().count();
+        if room > 0 {
+            let name = if len > room {
+                let skip = len - room + 1;
+                format!("…{}", full_name.chars().skip(skip).collect::<String>())
+            } else {
+                full_name
+            };
+            buf.set_stringn(area.x + name_x as u16, area.y, name, room, Style::new());
+        }
+        if position_x >= name_x {
+            buf.set_string(area.x + position_x as u16, area.y, position, Style::new());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keybind::parse_key_sequence;
+
+    fn active_text(engine: &Engine) -> String {
+        let state = engine.state();
+        let view = &state.views[&state.active_view];
+        state.buffers[&view.buffer].contents.to_string()
+    }
+
+    #[test]
+    fn headless_engine_takes_its_size() {
+        let engine = Engine::new_headless(40, 10).unwrap();
+        let state = engine.state();
+        assert_eq!((state.size.width, state.size.height), (40, 10));
+        // The command and status lines take a row each
+        let view = &state.views[&state.active_view];
+        assert_eq!((view.size.width, view.size.height), (40, 8));
+    }
+
+    #[test]
+    fn headless_engine_runs_commands() {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        engine.execute_command("insert \"ab\\ncd\"").unwrap();
+        assert_eq!(active_text(&engine), "ab\ncd");
+
+        engine.execute_command("goto-start").unwrap();
+        engine.execute_command("delete").unwrap();
+        assert_eq!(active_text(&engine), "b\ncd");
+
+        engine.execute_command("undo").unwrap();
+        assert_eq!(active_text(&engine), "ab\ncd");
+        assert!(engine.execute_command("no-such-command").is_err());
+    }
+
+    fn press(engine: &Engine, c: char) {
+        let key = KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        engine.event(Event::Key(key)).unwrap();
+    }
+
+    #[test]
+    fn bound_commands_see_each_others_effects() {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        engine.execute_command("insert \"abc\"").unwrap();
+        engine.execute_command("goto-start").unwrap();
+
+        // Each command records the mode and text it runs in
+        let log = Rc::new(RefCell::new(vec![]));
+        let record = Command::new("record", "", {
+            let log = log.clone();
+            move |engine: Engine| {
+                let mode = engine.state().current_mode.to_string();
+                RefCell::borrow_mut(&log).push(format!("{mode} {}", active_text(&engine)));
+            }
+        });
+        let mut state = engine.state_mut();
+        state.commands.insert("record".into(), record);
+        let bind = |state: &mut EngineState, key, commands: &[&str]| {
+            let commands = commands.iter().map(|c| c.to_string()).collect();
+            let key = parse_key_sequence(key).unwrap();
+            state.keybinds.bind(&Mode::Normal, &key, commands);
+        };
+        bind(&mut state, "d", &["delete", "enter-mode insert"]);
+        bind(
+            &mut state,
+            "D",
+            &["record", "delete", "record", "enter-mode insert", "record"],
+        );
+        drop(state);
+
+        press(&engine, 'd');
+        assert_eq!(active_text(&engine), "bc");
+        assert!(matches!(engine.state().current_mode, Mode::Insert));
+        // Typed where the deleted char was
+        press(&engine, 'x');
+        assert_eq!(active_text(&engine), "xbc");
+
+        // The cursor is now on the b, after the typed x
+        engine.state_mut().current_mode = Mode::Normal;
+        press(&engine, 'D');
+        assert_eq!(
+            *log.borrow(),
+            ["NORMAL xbc", "NORMAL xc", "INSERT xc"].map(String::from)
+        );
+    }
+
+    #[test]
+    fn opening_a_missing_file_is_an_error() {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        let path = std::env::temp_dir().join("spiral-test-missing-file");
+        let _ = std::fs::remove_file(&path);
+
+        let error = engine.open(&path).unwrap_err().to_string();
+        assert!(error.starts_with(&path.display().to_string()), "{error}");
+        assert_eq!(engine.state().views.len(), 1);
+    }
+
+    #[test]
+    fn quit_is_bound_only_when_the_config_fails() {
+        let path = std::env::temp_dir().join("spiral-test-config.lua");
+        let options = Options {
+            config: Some(path.clone()),
+            ..Options::default()
+        };
+        let engine = Engine::new(
+            options,
+            Size {
+                width: 80,
+                height: 24,
+            },
+        )
+        .unwrap();
+        let quit = parse_key_sequence("C-q").unwrap();
+        let quit_bound = || engine.state().keybinds.get(&Mode::Normal, &quit).is_some();
+        assert!(quit_bound());
+
+        std::fs::write(&path, "").unwrap();
+        engine.reload_config().unwrap();
+        assert!(!quit_bound());
+
+        std::fs::write(&path, "error('broken')").unwrap();
+        assert!(engine.reload_config().is_err());
+        assert!(quit_bound());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn path_positions_are_split_off() {
+        let missing = |arg: &str| parse_path_position(&format!("/nonexistent/{arg}"));
+        let path = |p: &str| PathBuf::from(format!("/nonexistent/{p}"));
+
+        assert_eq!(missing("a.rs"), (path("a.rs"), None));
+        assert_eq!(missing("a.rs:12"), (path("a.rs"), Some((11, 0))));
+        assert_eq!(missing("a.rs:12:5"), (path("a.rs"), Some((11, 4))));
+        assert_eq!(missing("a.rs:0:0"), (path("a.rs"), Some((0, 0))));
+        assert_eq!(missing("a.rs:x"), (path("a.rs:x"), None));
+    }
+
+    #[test]
+    fn existing_paths_with_colons_are_kept_whole() {
+        let file = std::env::temp_dir().join("spiral-test-a:1");
+        std::fs::write(&file, "").unwrap();
+        let name = file.display().to_string();
+
+        assert_eq!(parse_path_position(&name), (file.clone(), None));
+        assert_eq!(
+            parse_path_position(&format!("{name}:3")),
+            (file.clone(), Some((2, 0)))
+        );
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn positions_past_the_end_are_clamped() {
+        let file = std::env::temp_dir().join("spiral-test-position.txt");
+        std::fs::write(&file, "ab\ncd\n").unwrap();
+        let engine = Engine::new_headless(80, 24).unwrap();
+
+        for ((line, col), head) in [((0, 99), 2), ((1, 1), 4), ((99, 0), 6)] {
+            let view = engine.open_at(&file, Some((line, col))).unwrap();
+            let selections = &engine.state().views[&view].selections;
+            assert_eq!((selections[0].start, selections[0].end), (head, head));
+        }
+        std::fs::remove_file(file).unwrap();
     }
 }
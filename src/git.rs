@@ -0,0 +1,103 @@
+use std::{ops::Range, path::Path};
+
+use ratatui::style::Color;
+use ropey::Rope;
+
+use crate::sign::Sign;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HunkKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// A changed region of a buffer compared to the file in git
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Hunk {
+    pub kind: HunkKind,
+    /// Lines of the buffer that changed. For deletions this is the single line
+    /// after which the lines were removed.
+    pub lines: Range<usize>,
+}
+
+impl Hunk {
+    pub fn sign(&self) -> Sign {
+        let (glyph, color) = match self.kind {
+            HunkKind::Added => ('+', Color::Green),
+            HunkKind::Modified => ('~', Color::Yellow),
+            HunkKind::Deleted => ('-', Color::Red),
+        };
+        Sign { glyph, color }
+    }
+}
+
+/// Contents of a file as staged in git, or as of `HEAD` if it isn't staged.
+/// `None` if the file isn't tracked by git.
+#[cfg(feature = "git")]
+pub fn read_base(path: &Path) -> Option<Vec<u8>> {
+    let path = path.canonicalize().ok()?;
+    let repo = git2::Repository::discover(path.parent()?).ok()?;
+    let relative = path.strip_prefix(repo.workdir()?).ok()?;
+    let id = match repo.index().ok()?.get_path(relative, 0) {
+        Some(entry) => entry.id,
+        None => repo
+            .head()
+            .ok()?
+            .peel_to_tree()
+            .ok()?
+            .get_path(relative)
+            .ok()?
+            .id(),
+    };
+    Some(repo.find_blob(id).ok()?.content().to_vec())
+}
+
+#[cfg(not(feature = "git"))]
+pub fn read_base(_path: &Path) -> Option<Vec<u8>> {
+    None
+}
+
+/// The hunks where `contents` differs from `base`
+#[cfg(feature = "git")]
+pub fn diff(base: &[u8], contents: &Rope) -> Vec<Hunk> {
+    let contents = contents.bytes().collect::<Vec<_>>();
+    let mut options = git2::DiffOptions::new();
+    options.context_lines(0);
+    let Ok(patch) = git2::Patch::from_buffers(base, None, &contents, None, Some(&mut options))
+    else {
+        return vec![];
+    };
+
+    (0..patch.num_hunks())
+        .filter_map(|i| patch.hunk(i).ok())
+        .map(|(hunk, _)| {
+            // Line numbers are 1-based; a deletion starts at the line before it,
+            // which is 0 at the top of the file
+            let start = hunk.new_start() as usize;
+            let count = hunk.new_lines() as usize;
+            if count == 0 {
+                let line = start.saturating_sub(1);
+                Hunk {
+                    kind: HunkKind::Deleted,
+                    lines: line..line + 1,
+                }
+            } else {
+                let kind = if hunk.old_lines() == 0 {
+                    HunkKind::Added
+                } else {
+                    HunkKind::Modified
+                };
+                Hunk {
+                    kind,
+                    lines: start - 1..start - 1 + count,
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "git"))]
+pub fn diff(_base: &[u8], _contents: &Rope) -> Vec<Hunk> {
+    vec![]
+}
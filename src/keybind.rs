@@ -10,6 +10,26 @@ pub struct Keybindings {
 }
 
 impl Keybindings {
+    /// Bindings in place before any config is loaded
+    pub fn builtin() -> Self {
+        let mut keybinds = Self {
+            binds: HashMap::new(),
+        };
+        keybinds.bind_fallbacks();
+        keybinds
+    }
+
+    /// Binds C-q to quit where it is unbound, so that a broken config still
+    /// leaves a way to quit
+    pub fn bind_fallbacks(&mut self) {
+        let quit = parse_key_sequence("C-q").unwrap();
+        for mode in [Mode::Normal, Mode::Insert] {
+            if self.get(&mode, &quit).is_none() {
+                self.bind(&mode, &quit, vec!["quit".into()]);
+            }
+        }
+    }
+
     pub fn get(&self, mode: &Mode, seq: &[Key]) -> Option<&Binding> {
         let mut map = self.binds.get(mode)?;
         let mut binding = None;
@@ -70,6 +90,42 @@ impl Keybindings {
         }
         map.insert(key, Binding::Commands(commands));
     }
+
+    /// Key sequences that run `command` in each mode, e.g. `g a`
+    pub fn sequences_for(&self, command: &str) -> Vec<(Mode, String)> {
+        fn walk(
+            map: &HashMap<Key, Binding>,
+            command: &str,
+            seq: &mut Vec<Key>,
+            out: &mut Vec<String>,
+        ) {
+            for (key, binding) in map {
+                seq.push(*key);
+                match binding {
+                    Binding::Group(map) => walk(map, command, seq, out),
+                    Binding::Commands(cmds) => {
+                        let runs = cmds
+                            .iter()
+                            .any(|cmd| cmd.split_whitespace().next() == Some(command));
+                        if runs {
+                            let keys = seq.iter().map(Key::to_string).collect::<Vec<_>>();
+                            out.push(keys.join(" "));
+                        }
+                    }
+                }
+                seq.pop();
+            }
+        }
+
+        let mut found = vec![];
+        for (mode, map) in &self.binds {
+            let mut seqs = vec![];
+            walk(map, command, &mut vec![], &mut seqs);
+            found.extend(seqs.into_iter().map(|seq| (mode.clone(), seq)));
+        }
+        found.sort_by(|a, b| (a.0.to_string(), &a.1).cmp(&(b.0.to_string(), &b.1)));
+        found
+    }
 }
 
 pub enum Binding {
@@ -187,6 +243,8 @@ pub fn parse_key_sequence(seq: &str) -> anyhow::Result<Vec<Key>> {
                 "spc" => KeyCode::Char(' '),
                 "bspc" => KeyCode::Backspace,
                 "enter" => KeyCode::Enter,
+                "insert" => KeyCode::Insert,
+                "home" => KeyCode::Home,
                 _ if key.chars().count() == 1 => KeyCode::Char(key.chars().next().unwrap()),
                 _ => anyhow::bail!("unrecognized key {key}"),
             };
@@ -7,8 +7,15 @@ impl KillRing {
         Self { entries: vec![] }
     }
 
-    pub fn add_entry(&mut self, entry: KillRingEntry) {
+    /// Adds an entry, dropping the oldest ones beyond `max`
+    pub fn add_entry(&mut self, entry: KillRingEntry, max: usize) {
         self.entries.push(entry);
+        let excess = self.entries.len().saturating_sub(max);
+        self.entries.drain(..excess);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
     }
 
     pub fn get(&self) -> Option<&KillRingEntry> {
@@ -31,15 +38,23 @@ impl KillRing {
 
 pub struct KillRingEntry {
     pub text: Vec<String>,
+    /// Whether the entry holds whole lines, which are pasted on lines of their own
+    pub linewise: bool,
 }
 
 impl KillRingEntry {
     pub fn new<S: Into<String>, I: IntoIterator<Item = S>>(items: I) -> Self {
         Self {
             text: items.into_iter().map(Into::into).collect(),
+            linewise: false,
         }
     }
 
+    pub fn linewise(mut self, linewise: bool) -> Self {
+        self.linewise = linewise;
+        self
+    }
+
     pub fn get_for_cursor_count(&self, count: usize) -> Vec<&str> {
         self.text
             .iter()
@@ -51,3 +66,22 @@ impl KillRingEntry {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_beyond_max_are_dropped_oldest_first() {
+        let mut ring = KillRing::new();
+        for text in ["a", "b", "c"] {
+            ring.add_entry(KillRingEntry::new([text]), 2);
+        }
+        let texts = ring
+            .entries
+            .iter()
+            .map(|e| e.text[0].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["b", "c"]);
+    }
+}
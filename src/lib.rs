@@ -0,0 +1,43 @@
+#![feature(try_blocks)]
+#![feature(macro_metavar_expr)]
+#![feature(iterator_try_collect)]
+#![feature(let_chains)]
+#![feature(iter_intersperse)]
+#![feature(get_many_mut)]
+
+pub mod buffer;
+pub mod charclass;
+pub mod command;
+pub mod diff;
+pub mod digraph;
+pub mod engine;
+pub mod git;
+pub mod keybind;
+pub mod kill_ring;
+pub mod lua;
+pub mod message;
+pub mod mode;
+pub mod options;
+pub mod process;
+pub mod selection;
+pub mod sign;
+pub mod view;
+
+use std::path::PathBuf;
+
+#[derive(clap::Parser, Default)]
+pub struct Options {
+    pub path: Option<PathBuf>,
+    /// Load only this config file
+    #[arg(long, short)]
+    pub config: Option<PathBuf>,
+    /// Don't load the system or user config
+    #[arg(long)]
+    pub ignore_global_config: bool,
+    /// File of commands to run after opening `path`
+    #[arg(long)]
+    pub exec: Option<PathBuf>,
+    /// Read `path` (or stdin), run `--exec` on it and write the result to stdout
+    #[arg(long)]
+    pub filter: bool,
+}
@@ -1,15 +1,20 @@
-use std::{cell::RefCell, clone, rc::Rc};
+use std::{cell::RefCell, clone, ops::Range, rc::Rc};
 
 use log::debug;
-use mlua::{FromLua, MultiValue, Table, UserData};
+use mlua::{FromLua, MetaMethod, MultiValue, Table, UserData};
+use ratatui::style::Color;
 use ropey::Rope;
 
 use crate::{
-    buffer::{Buffer, BufferId},
-    engine::{self, Engine},
+    buffer::{Buffer, BufferBacking, BufferId},
+    command::{search_matches, CommandArg},
+    engine::{self, Engine, EngineState, ModeStyle, PromptTarget},
     keybind::{parse_key_sequence, Key},
+    message::MessageLevel,
     mode::Mode,
+    process::{Process, ProcessId},
     selection::Selection,
+    sign::{Sign, SignGroup},
     view::{View, ViewId},
 };
 
@@ -184,8 +189,23 @@ pub fn init_lua(engine: Engine) -> anyhow::Result<()> {
             e.execute_command(&cmd).map_err(mlua::Error::external)?;
         }
 
+        fn try_exec(e, cmd: String) {
+            match e.execute_command(&cmd) {
+                Ok(()) => (true, None),
+                Err(err) => (false, Some(err.to_string())),
+            }
+        }
+
+        fn require(e, path: String) {
+            e.require_lua(path).map_err(mlua::Error::external)?;
+        }
+
+        fn source(e, path: String) {
+            e.source(path).map_err(mlua::Error::external)?;
+        }
+
         fn open_file(e, path: String) {
-            e.open(path);
+            e.open(path).map_err(mlua::Error::external)?;
         }
 
         fn create_buffer(e) {
@@ -199,19 +219,208 @@ pub fn init_lua(engine: Engine) -> anyhow::Result<()> {
         }
 
         fn set_active_view(e, view_ref: ViewRef) {
-            e.state_mut().active_view = view_ref.id;
+            e.state_mut().set_active_view(view_ref.id);
         }
 
         fn get_active_view(e) {
             ViewRef { id: e.active_view() }
         }
 
+        fn message(e, text: String, level: Option<String>) {
+            let level = match level {
+                Some(level) => level.parse().map_err(mlua::Error::external)?,
+                None => MessageLevel::Info,
+            };
+            e.state_mut().message(level, text);
+        }
+
+        fn spawn(e, cmd: String, args: Vec<String>, on_output: mlua::Function<'static>) {
+            let process = Process::spawn(&cmd, &args, on_output).map_err(mlua::Error::external)?;
+            let id = ProcessId::generate();
+            e.state_mut().processes.insert(id, process);
+            ProcessRef { id }
+        }
+
+        fn request_redraw(e) {
+            e.request_redraw();
+        }
+
+        fn get_mode(e) {
+            let mode = e.state().current_mode.to_string().to_lowercase();
+            mode
+        }
+
         fn get_views(e) {
             let views = e.state().views.keys().copied().map(|id| ViewRef { id }).collect::<Vec<_>>();
             views
         }
+
+        fn set_whitespace_chars(e, space: String, tab: String) {
+            let chars = [space, tab].map(|s| s.chars().next());
+            let [Some(space), Some(tab)] = chars else {
+                Err(mlua::Error::runtime("whitespace glyphs cannot be empty"))?
+            };
+            let mut state = e.state_mut();
+            state.whitespace_chars.space = space;
+            state.whitespace_chars.tab = tab;
+        }
+
+        fn set_selection_color(e, color: String) {
+            e.state_mut().selection_colors.selection = parse_color(&color)?;
+        }
+
+        fn set_secondary_selection_color(e, color: String) {
+            e.state_mut().selection_colors.secondary_selection = parse_color(&color)?;
+        }
+
+        fn set_secondary_cursor_color(e, color: String) {
+            e.state_mut().selection_colors.secondary_cursor = parse_color(&color)?;
+        }
+
+        fn set_unfocused_cursor_color(e, color: Option<String>) {
+            let color = color.map(|c| parse_color(&c)).transpose()?;
+            e.state_mut().selection_colors.unfocused_cursor = color;
+        }
+
+        fn set_cursor_line_color(e, color: String) {
+            e.state_mut().selection_colors.cursor_line = parse_color(&color)?;
+        }
+
+        fn set_cursor_color(e, color: String, mode: Option<String>) {
+            let color = parse_color(&color)?;
+            let mut state = e.state_mut();
+            let colors = &mut state.selection_colors;
+            match mode {
+                Some(mode) => {
+                    let mode = mode.parse().map_err(mlua::Error::external)?;
+                    colors.cursor.insert(mode, color);
+                }
+                None => {
+                    colors.cursor.clear();
+                    colors.cursor_fallback = color;
+                }
+            }
+        }
+
+        fn set_mode_style(e, mode: String, name: Option<String>, color: Option<String>) {
+            let mode = mode.parse().map_err(mlua::Error::external)?;
+            let color = color.map(|c| parse_color(&c)).transpose()?;
+            e.state_mut().mode_styles.insert(mode, ModeStyle { name, color });
+        }
+
+        fn set_pairs(e, pairs: Vec<String>) {
+            let pairs = pairs
+                .iter()
+                .map(|pair| {
+                    let mut chars = pair.chars();
+                    match (chars.next(), chars.next(), chars.next()) {
+                        (Some(open), Some(close), None) => Ok((open, close)),
+                        _ => Err(mlua::Error::runtime(format!(
+                            "pair {pair:?} must be exactly two characters"
+                        ))),
+                    }
+                })
+                .collect::<mlua::Result<_>>()?;
+            e.state_mut().pairs = pairs;
+        }
+
+        fn set_option(e, name: String, value: CommandArg) {
+            e.state_mut().editor_options.set(&name, value).map_err(mlua::Error::external)?;
+        }
+
+        fn get_option(e, name: String) {
+            let value = e.state().editor_options.get(&name).map_err(mlua::Error::external)?;
+            value
+        }
+
+        fn set_buffer_option(e, buffer_ref: BufferRef, name: String, value: Option<CommandArg>) {
+            let mut state = e.state_mut();
+            let buffer = state
+                .buffers
+                .get_mut(&buffer_ref.id)
+                .ok_or(mlua::Error::runtime("no buffer found for buffer id"))?;
+            buffer.options.set(&name, value).map_err(mlua::Error::external)?;
+        }
+
+        fn get_buffer_option(e, buffer_ref: BufferRef, name: String) {
+            let value = e.state().buffer_options(buffer_ref.id).get(&name).map_err(mlua::Error::external)?;
+            value
+        }
+
+        fn set_sign(e, buffer_ref: BufferRef, line: usize, glyph: String, color: String) {
+            let mut chars = glyph.chars();
+            let (Some(glyph), None) = (chars.next(), chars.next()) else {
+                Err(mlua::Error::runtime(format!("sign {glyph:?} must be exactly one character")))?
+            };
+            let color = parse_color(&color)?;
+            let mut state = e.state_mut();
+            let buffer = state
+                .buffers
+                .get_mut(&buffer_ref.id)
+                .ok_or(mlua::Error::runtime("no buffer found for buffer id"))?;
+            buffer.signs.set(SignGroup::Lua, line, Sign { glyph, color });
+        }
+
+        fn remove_sign(e, buffer_ref: BufferRef, line: usize) {
+            let mut state = e.state_mut();
+            let buffer = state
+                .buffers
+                .get_mut(&buffer_ref.id)
+                .ok_or(mlua::Error::runtime("no buffer found for buffer id"))?;
+            buffer.signs.remove(SignGroup::Lua, line);
+        }
+
+        fn for_each_selection(e, f: mlua::Function<'static>) {
+            let view_id = e.active_view();
+            let mut i = 0;
+            // The callback may add or remove selections, so re-check the count every time
+            while let Some(selection) = e.view(view_id).and_then(|v| v.selections.get(i).copied()) {
+                let userdata = lua.create_userdata(selection)?;
+                f.call::<_, ()>((userdata.clone(), i + 1))?;
+                let selection = *userdata.borrow::<Selection>()?;
+
+                let mut state = e.state_mut();
+                let Some(view) = state.views.get_mut(&view_id) else {
+                    break;
+                };
+                if let Some(sel) = view.selections.get_mut(i) {
+                    *sel = selection;
+                }
+                i += 1;
+            }
+
+            let mut state = e.state_mut();
+            let state = &mut *state;
+            if let Some(view) = state.views.get_mut(&view_id) {
+                let buffer = &state.buffers[&view.buffer];
+                for sel in &mut view.selections {
+                    sel.make_valid(&buffer.contents);
+                }
+                view.merge_overlapping_selections();
+                let scrolloff = state.editor_options.with_overrides(&buffer.options).scrolloff;
+                view.make_selection_visisble(buffer, scrolloff);
+            }
+        }
+
+        fn prompt(e, prompt: String, on_enter: mlua::Function<'static>) {
+            e.state_mut().cli.open(prompt, PromptTarget::Lua(on_enter));
+        }
+
+        fn on(e, event: String, hook: mlua::Function<'static>) {
+            e.add_event_hook(event, hook).map_err(mlua::Error::external)?;
+        }
+
+        fn on_filetype(e, language: String, hook: mlua::Function<'static>) {
+            e.state_mut().filetype_hooks.entry(language).or_default().push(hook);
+        }
+
+        fn set_node_kinds(e, language: String, category: String, kinds: Vec<String>) {
+            e.state_mut().node_kinds.insert((language, category), kinds);
+        }
     }
 
+    engine_table.raw_set("options", OptionsRef { buffer: None })?;
+
     lua.globals().raw_set("Editor", engine_table)?;
 
     Ok(())
@@ -219,15 +428,169 @@ pub fn init_lua(engine: Engine) -> anyhow::Result<()> {
 
 #[derive(Clone, Copy)]
 pub struct BufferRef {
-    id: BufferId,
+    pub id: BufferId,
+}
+
+impl BufferRef {
+    /// Calls `f` with the buffer, failing if it has been closed
+    fn with<R>(&self, lua: &mlua::Lua, f: impl FnOnce(&Buffer) -> R) -> mlua::Result<R> {
+        let engine = lua.engine();
+        let state = engine.state();
+        let buffer = state
+            .buffer(self.id)
+            .ok_or(mlua::Error::runtime("no buffer found for buffer id"))?;
+        Ok(f(buffer))
+    }
+
+    /// Char ranges of the matches of `pattern`, a regex unless `literal`.
+    /// Empty matches are skipped, as an inclusive end can't express them.
+    fn find_all(
+        &self,
+        lua: &mlua::Lua,
+        pattern: &str,
+        literal: Option<bool>,
+    ) -> mlua::Result<Vec<Range<usize>>> {
+        let pattern = if literal.unwrap_or(false) {
+            regex::escape(pattern)
+        } else {
+            pattern.to_string()
+        };
+        let matches = self
+            .with(lua, |buffer| search_matches(buffer, &pattern))?
+            .map_err(mlua::Error::external)?;
+        Ok(matches.into_iter().filter(|m| !m.is_empty()).collect())
+    }
+}
+
+/// A match as a `{ start, end }` table, `end` inclusive like a selection's
+fn match_table(lua: &mlua::Lua, m: Range<usize>) -> mlua::Result<Table<'_>> {
+    lua.create_table_from([("start", m.start), ("end", m.end - 1)])
 }
 
 impl UserData for BufferRef {
     fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
-        fields.add_field_method_get("id", |_, buffer_ref| Ok(buffer_ref.id.0))
+        fields.add_field_method_get("id", |_, buffer_ref| Ok(buffer_ref.id.0));
+        fields.add_field_method_get("name", |lua, buffer_ref| {
+            buffer_ref.with(lua, |buffer| buffer.name.clone())
+        });
+        fields.add_field_method_get("path", |lua, buffer_ref| {
+            buffer_ref.with(lua, |buffer| match &buffer.backing {
+                BufferBacking::File(path) => Some(path.to_string_lossy().into_owned()),
+                BufferBacking::None => None,
+            })
+        });
+        fields.add_field_method_get("filetype", |lua, buffer_ref| {
+            buffer_ref.with(lua, |buffer| buffer.language.clone())
+        });
+        fields.add_field_method_get("is_modified", |lua, buffer_ref| {
+            buffer_ref.with(lua, Buffer::is_modified)
+        });
+        fields.add_field_method_get("options", |_, buffer_ref| {
+            Ok(OptionsRef {
+                buffer: Some(buffer_ref.id),
+            })
+        });
     }
 
-    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {}
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Matches are returned as `{ start, end }` tables with `end` inclusive
+        // like a selection's, so they can be passed to `add_selection`
+        methods.add_method(
+            "find",
+            |lua, buffer_ref, (pattern, from_char, literal): (String, usize, Option<bool>)| {
+                let matches = buffer_ref.find_all(lua, &pattern, literal)?;
+                matches
+                    .into_iter()
+                    .find(|m| m.start >= from_char)
+                    .map(|m| match_table(lua, m))
+                    .transpose()
+            },
+        );
+
+        methods.add_method(
+            "find_all",
+            |lua, buffer_ref, (pattern, literal): (String, Option<bool>)| {
+                buffer_ref
+                    .find_all(lua, &pattern, literal)?
+                    .into_iter()
+                    .map(|m| match_table(lua, m))
+                    .collect::<mlua::Result<Vec<_>>>()
+            },
+        );
+
+        methods.add_method("node_at", |lua, buffer_ref, char_index: usize| {
+            let engine = lua.engine();
+            let state = engine.state();
+            let buffer = state
+                .buffer(buffer_ref.id)
+                .ok_or(mlua::Error::runtime("no buffer found for buffer id"))?;
+            if !buffer.syntax {
+                return Ok(None);
+            }
+
+            let text = &buffer.contents;
+            let byte = text.char_to_byte(char_index.min(text.len_chars()));
+            let Some(node) = buffer
+                .tree
+                .root_node()
+                .descendant_for_byte_range(byte, byte)
+            else {
+                return Ok(None);
+            };
+            let range = node.byte_range();
+            Ok(Some(NodeInfo {
+                kind: node.kind().to_string(),
+                start: text.byte_to_char(range.start),
+                end: text.byte_to_char(range.end).saturating_sub(1),
+                parent_kind: node.parent().map(|p| p.kind().to_string()),
+            }))
+        });
+    }
+}
+
+/// Handle to a process started with `Editor.spawn`
+pub struct ProcessRef {
+    id: ProcessId,
+}
+
+impl UserData for ProcessRef {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("send", |lua, process_ref, line: String| {
+            let engine = lua.engine();
+            let mut state = engine.state_mut();
+            let process = state
+                .processes
+                .get_mut(&process_ref.id)
+                .ok_or(mlua::Error::runtime("process has exited"))?;
+            process.send(&line).map_err(mlua::Error::external)
+        });
+
+        methods.add_method("kill", |lua, process_ref, ()| {
+            let engine = lua.engine();
+            // Dropping the process kills it
+            let process = engine.state_mut().processes.remove(&process_ref.id);
+            drop(process);
+            Ok(())
+        });
+    }
+}
+
+/// Read-only snapshot of a tree-sitter node. `start` and `end` are char
+/// indices, with `end` inclusive like a selection's.
+pub struct NodeInfo {
+    kind: String,
+    start: usize,
+    end: usize,
+    parent_kind: Option<String>,
+}
+
+impl UserData for NodeInfo {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("kind", |_, node| Ok(node.kind.clone()));
+        fields.add_field_method_get("start", |_, node| Ok(node.start));
+        fields.add_field_method_get("end", |_, node| Ok(node.end));
+        fields.add_field_method_get("parent_kind", |_, node| Ok(node.parent_kind.clone()));
+    }
 }
 
 impl<'lua> FromLua<'lua> for BufferRef {
@@ -239,9 +602,57 @@ impl<'lua> FromLua<'lua> for BufferRef {
     }
 }
 
+/// Table-like access to the editor options, or to one buffer's overrides.
+/// Reading a buffer option gives its effective value; assigning `nil` to one
+/// clears the override.
+#[derive(Clone, Copy)]
+pub struct OptionsRef {
+    buffer: Option<BufferId>,
+}
+
+impl UserData for OptionsRef {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::Index, |lua, options, name: String| {
+            let engine = lua.engine();
+            let state = engine.state();
+            let value = match options.buffer {
+                Some(id) => {
+                    if !state.buffers.contains_key(&id) {
+                        return Err(mlua::Error::runtime("no buffer found for buffer id"));
+                    }
+                    state.buffer_options(id).get(&name)
+                }
+                None => state.editor_options.get(&name),
+            };
+            value.map_err(mlua::Error::external)
+        });
+
+        methods.add_meta_method(
+            MetaMethod::NewIndex,
+            |lua, options, (name, value): (String, Option<CommandArg>)| {
+                let engine = lua.engine();
+                let mut state = engine.state_mut();
+                match options.buffer {
+                    Some(id) => state
+                        .buffers
+                        .get_mut(&id)
+                        .ok_or(mlua::Error::runtime("no buffer found for buffer id"))?
+                        .options
+                        .set(&name, value),
+                    None => match value {
+                        Some(value) => state.editor_options.set(&name, value),
+                        None => Err(anyhow::anyhow!("option {name} can't be nil")),
+                    },
+                }
+                .map_err(mlua::Error::external)
+            },
+        );
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct ViewRef {
-    id: ViewId,
+    pub id: ViewId,
 }
 
 impl UserData for ViewRef {
@@ -263,6 +674,21 @@ impl UserData for ViewRef {
     }
 
     fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("buffer", |lua, view_ref, ()| {
+            let engine = lua.engine();
+            let view = engine
+                .view(view_ref.id)
+                .ok_or(mlua::Error::runtime("no view found for view id"))?;
+            Ok(BufferRef { id: view.buffer })
+        });
+
+        methods.add_method("set_buffer", |lua, view_ref, buffer_ref: BufferRef| {
+            lua.engine()
+                .state_mut()
+                .set_view_buffer(view_ref.id, buffer_ref.id)
+                .map_err(mlua::Error::external)
+        });
+
         methods.add_method("get_selections", |lua, view_ref, ()| {
             let engine = lua.engine();
             let view = engine
@@ -280,13 +706,14 @@ impl UserData for ViewRef {
                 let mut state = engine.state_mut();
                 let view = state.views.get_mut(&view_ref.id).unwrap();
                 view.selections = selections;
+                view.clamp_primary();
 
                 Ok(())
             },
         );
 
         methods.add_method("add_selection", |lua, view_ref, selection: Table| {
-            let mut selection = if selection.contains_key("start")? {
+            let selection = if selection.contains_key("start")? {
                 let start: usize = selection.get("start")?;
                 let end: usize = selection.get("end")?;
                 let dir = selection.get("direction")?;
@@ -301,35 +728,99 @@ impl UserData for ViewRef {
                 let head: usize = selection.get("head")?;
                 let anchor: usize = selection.get("anchor")?;
 
+                let (start, end, dir) = if head < anchor {
+                    (head, anchor, crate::selection::Direction::Back)
+                } else {
+                    (anchor, head, crate::selection::Direction::Forward)
+                };
                 Selection {
                     view: view_ref.id,
-                    start: head,
-                    end: anchor,
-                    dir: crate::selection::Direction::Forward,
+                    start,
+                    end,
+                    dir,
                 }
             } else {
-                todo!()
+                return Err(mlua::Error::runtime(
+                    "selection needs either start and end, or head and anchor",
+                ));
             };
 
-            let engine = lua.engine();
-            let mut state = engine.state_mut();
-            let view = state.view(view_ref.id).unwrap();
-            let buffer = state.buffer(view.buffer).unwrap();
-
-            selection.make_valid(&buffer.contents);
-
-            state
-                .views
-                .get_mut(&view_ref.id)
-                .unwrap()
-                .selections
-                .push(selection);
-
+            add_selection(lua, *view_ref, selection)?;
             Ok(())
         });
+
+        methods.add_method(
+            "select_char_range",
+            |lua, view_ref, (start, end): (usize, usize)| {
+                if start > end {
+                    return Err(mlua::Error::runtime(format!(
+                        "invalid char range {start}..{end}"
+                    )));
+                }
+                let selection = Selection {
+                    view: view_ref.id,
+                    start,
+                    end,
+                    dir: crate::selection::Direction::Forward,
+                };
+                add_selection(lua, *view_ref, selection)
+            },
+        );
+
+        methods.add_method(
+            "select_lines",
+            |lua, view_ref, (start_line, end_line): (usize, usize)| {
+                if start_line > end_line {
+                    return Err(mlua::Error::runtime(format!(
+                        "invalid line range {start_line}..{end_line}"
+                    )));
+                }
+                let (start, end) = {
+                    let engine = lua.engine();
+                    let state = engine.state();
+                    let text = &view_buffer(&state, *view_ref)?.contents;
+                    let last_line = text.len_lines() - 1;
+                    let start = text.line_to_char(start_line.min(last_line));
+                    // Up to and including the newline of the last line
+                    let end = text.line_to_char((end_line + 1).min(last_line + 1));
+                    (start, end.saturating_sub(1).max(start))
+                };
+                let selection = Selection {
+                    view: view_ref.id,
+                    start,
+                    end,
+                    dir: crate::selection::Direction::Forward,
+                };
+                add_selection(lua, *view_ref, selection)
+            },
+        );
     }
 }
 
+fn view_buffer(state: &EngineState, view_ref: ViewRef) -> mlua::Result<&Buffer> {
+    let view = state
+        .view(view_ref.id)
+        .ok_or(mlua::Error::runtime("no view found for view id"))?;
+    Ok(state.buffer(view.buffer).unwrap())
+}
+
+/// Adds `selection` to the view, clamped to the buffer, and returns it
+fn add_selection(
+    lua: &mlua::Lua,
+    view_ref: ViewRef,
+    mut selection: Selection,
+) -> mlua::Result<Selection> {
+    let engine = lua.engine();
+    let mut state = engine.state_mut();
+    selection.make_valid(&view_buffer(&state, view_ref)?.contents);
+
+    let view = state.views.get_mut(&view_ref.id).unwrap();
+    view.selections.push(selection);
+    view.merge_overlapping_selections();
+
+    Ok(selection)
+}
+
 impl<'lua> FromLua<'lua> for ViewRef {
     fn from_lua(value: mlua::Value<'lua>, lua: &'lua mlua::Lua) -> mlua::Result<Self> {
         Ok(*value
@@ -339,6 +830,13 @@ impl<'lua> FromLua<'lua> for ViewRef {
     }
 }
 
+/// Parses a named (`"red"`, `"dark gray"`), indexed (`"42"`) or hex (`"#ff8800"`) color
+pub fn parse_color(color: &str) -> mlua::Result<Color> {
+    color
+        .parse()
+        .map_err(|_| mlua::Error::runtime(format!("invalid color {color}")))
+}
+
 pub trait GetEngine {
     fn engine(&self) -> Engine;
 }
@@ -348,3 +846,42 @@ impl<'lua> GetEngine for &'lua mlua::Lua {
         self.app_data_ref::<Engine>().unwrap().clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn add_selection_round_trips_head_and_anchor() {
+        for (head, anchor) in [(2, 8), (8, 2)] {
+            let engine = Engine::new_headless(80, 24).unwrap();
+            engine.execute_command(r#"insert "hello world""#).unwrap();
+            let lua = engine.state().lua;
+            let (got_head, got_anchor): (usize, usize) = lua
+                .load(format!(
+                    r#"
+                    local view = Editor.get_active_view()
+                    view:add_selection({{ head = {head}, anchor = {anchor} }})
+                    for _, s in ipairs(view:get_selections()) do
+                        if s.start == 2 then
+                            return s.head, s.anchor
+                        end
+                    end
+                    "#
+                ))
+                .eval()
+                .unwrap();
+            assert_eq!((got_head, got_anchor), (head, anchor));
+        }
+    }
+
+    #[test]
+    fn add_selection_rejects_table_without_positions() {
+        let engine = Engine::new_headless(80, 24).unwrap();
+        let lua = engine.state().lua;
+        let result = lua
+            .load("Editor.get_active_view():add_selection({ 1, 2 })")
+            .exec();
+        assert!(result.is_err());
+    }
+}
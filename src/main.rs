@@ -1,33 +1,16 @@
-#![feature(try_blocks)]
-#![feature(macro_metavar_expr)]
-#![feature(iterator_try_collect)]
 #![feature(panic_update_hook)]
-#![feature(let_chains)]
-#![feature(iter_intersperse)]
-#![feature(get_many_mut)]
-
-mod buffer;
-mod command;
-mod engine;
-mod keybind;
-mod kill_ring;
-mod lua;
-mod mode;
-mod selection;
-mod view;
 
 use std::{
     collections::HashMap,
     fs::File,
-    io::stdout,
-    path::{Path, PathBuf},
+    io::{stdin, stdout, IsTerminal, Stdout, Write},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
     sync::atomic::{AtomicUsize, Ordering},
     time::Duration,
 };
 
-use buffer::{Buffer, BufferId};
 use clap::Parser;
-use engine::Engine;
 use log::{debug, error, warn};
 use ratatui::{
     crossterm::{
@@ -42,16 +25,11 @@ use ratatui::{
     Terminal,
 };
 use ropey::Rope;
-use view::{View, ViewId};
-
-#[derive(clap::Parser)]
-struct Options {
-    path: Option<PathBuf>,
-    #[arg(long, short)]
-    config: Option<PathBuf>,
-    #[arg(long)]
-    ignore_global_config: bool,
-}
+use spiral::{
+    engine::{parse_path_position, Engine, NoConfig, Size},
+    message::MessageLevel,
+    Options,
+};
 
 fn main() {
     env_logger::Builder::from_default_env()
@@ -62,24 +40,63 @@ fn main() {
         .init();
 
     let mut options = Options::parse();
+    if options.filter {
+        if let Err(e) = filter(options) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let path = options.path.take();
+    let exec = options.exec.take();
 
-    let engine = Engine::new(options).unwrap();
+    let (width, height) = crossterm::terminal::size().unwrap();
+    let size = Size {
+        width: width as usize,
+        height: height as usize,
+    };
+
+    let engine = Engine::new(options, size).unwrap();
+    // Shown once the UI is up, since stderr is hidden behind the alternate screen
     if let Err(e) = engine.reload_config() {
-        eprintln!("{e}");
-        return;
+        error!("{e}");
+        engine
+            .state_mut()
+            .message(MessageLevel::Error, format!("Config error: {e}"));
     }
+    engine.enter_initial_mode();
     if let Some(path) = path {
-        engine.open(path);
+        let (path, position) = parse_path_position(&path.to_string_lossy());
+        if let Err(e) = engine.open_at(path, position) {
+            eprintln!("{e}");
+            return;
+        }
+    } else if !stdin().is_terminal() {
+        match Rope::from_reader(stdin().lock()) {
+            Ok(rope) => {
+                engine.open_contents("*stdin*".into(), rope);
+            }
+            Err(e) => {
+                eprintln!("Could not read stdin: {e}");
+                return;
+            }
+        }
+    }
+    if let Some(script) = exec {
+        if let Err(e) = engine.source(script) {
+            eprintln!("{e}");
+            return;
+        }
+        if engine.state().should_quit {
+            return;
+        }
     }
 
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout())).unwrap();
 
     std::panic::update_hook(|hook, info| {
-        let _ = disable_raw_mode();
-        let _ = stdout().execute(LeaveAlternateScreen);
-        let _ = stdout().execute(PopKeyboardEnhancementFlags);
-
+        restore_terminal();
         hook(info)
     });
 
@@ -91,19 +108,126 @@ fn main() {
             KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES,
         ));
 
+    // Unwinding releases any borrows of the engine state held by the code that
+    // panicked, so the buffers can still be read afterwards
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run(&engine, &mut terminal)));
+    restore_terminal();
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            error!("{e}");
+            eprintln!("{e}");
+            write_recovery_files(&engine);
+            std::process::exit(1);
+        }
+        Err(payload) => {
+            write_recovery_files(&engine);
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+/// Consecutive terminal errors after which the terminal is assumed to be gone
+const MAX_TERMINAL_ERRORS: usize = 16;
+
+fn run(engine: &Engine, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> anyhow::Result<()> {
+    let mut errors = 0;
     loop {
-        if crossterm::event::poll(Duration::from_millis(20)).unwrap() {
-            let event = crossterm::event::read().unwrap();
-            let exit = engine.event(event).unwrap();
-            if exit {
-                break;
+        if errors >= MAX_TERMINAL_ERRORS {
+            anyhow::bail!("Lost the terminal after {errors} errors in a row");
+        }
+
+        engine.poll_highlights();
+        engine.poll_diffs();
+        engine.poll_processes();
+        engine.poll_disk_changes();
+        if engine.needs_redraw() {
+            match terminal.draw(|frame| engine.draw(frame)) {
+                Ok(_) => errors = 0,
+                Err(e) => {
+                    error!("Could not draw: {e}");
+                    errors += 1;
+                    engine.request_redraw();
+                    continue;
+                }
             }
         }
 
-        terminal.draw(|frame| engine.draw(frame)).unwrap();
+        let timeout = engine.redraw_timeout().unwrap_or(Duration::from_secs(1));
+        let event = match crossterm::event::poll(timeout) {
+            Ok(true) => crossterm::event::read(),
+            Ok(false) => continue,
+            Err(e) => Err(e),
+        };
+        match event {
+            Ok(event) => {
+                errors = 0;
+                match engine.event(event) {
+                    Ok(true) => return Ok(()),
+                    Ok(false) => {}
+                    Err(e) => {
+                        error!("{e}");
+                        engine
+                            .state_mut()
+                            .message(MessageLevel::Error, e.to_string());
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Could not read input: {e}");
+                errors += 1;
+            }
+        }
     }
+}
 
+fn restore_terminal() {
     let _ = disable_raw_mode();
     let _ = stdout().execute(LeaveAlternateScreen);
     let _ = stdout().execute(PopKeyboardEnhancementFlags);
 }
+
+fn write_recovery_files(engine: &Engine) {
+    for path in engine.write_recovery_files() {
+        eprintln!("Unsaved changes written to {}", path.display());
+    }
+}
+
+/// Runs the editor as a text filter, without touching the terminal
+fn filter(mut options: Options) -> anyhow::Result<()> {
+    let path = options.path.take();
+    let exec = options.exec.take();
+
+    let engine = Engine::new(
+        options,
+        Size {
+            width: 80,
+            height: 24,
+        },
+    )?;
+    // The builtin commands are enough for a filter, but a broken config is still an error
+    match engine.reload_config() {
+        Err(e) if e.is::<NoConfig>() => {}
+        result => result?,
+    }
+
+    let view = match path {
+        Some(path) => engine.open(path)?,
+        None => {
+            let rope = Rope::from_reader(stdin().lock())?;
+            engine.open_contents("*stdin*".into(), rope)
+        }
+    };
+    let buffer = engine.view(view).unwrap().buffer;
+
+    if let Some(script) = exec {
+        engine.source(script)?;
+    }
+
+    let mut out = stdout().lock();
+    for chunk in engine.buffer(buffer).unwrap().contents.chunks() {
+        out.write_all(chunk.as_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::{fmt::Display, str::FromStr, time::Instant};
+
+use ratatui::style::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+impl MessageLevel {
+    pub fn color(self) -> Color {
+        match self {
+            MessageLevel::Info => Color::Reset,
+            MessageLevel::Warn => Color::Yellow,
+            MessageLevel::Error => Color::Red,
+        }
+    }
+}
+
+impl FromStr for MessageLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "info" => Ok(MessageLevel::Info),
+            "warn" | "warning" => Ok(MessageLevel::Warn),
+            "error" => Ok(MessageLevel::Error),
+            o => anyhow::bail!("Unknown message level {o}"),
+        }
+    }
+}
+
+impl Display for MessageLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageLevel::Info => f.write_str("info"),
+            MessageLevel::Warn => f.write_str("warn"),
+            MessageLevel::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// A message shown in the command line and kept for the `messages` command
+pub struct Message {
+    pub level: MessageLevel,
+    pub text: String,
+    pub time: Instant,
+}
+
+impl Message {
+    pub fn new(level: MessageLevel, text: impl Into<String>) -> Self {
+        Self {
+            level,
+            text: text.into(),
+            time: Instant::now(),
+        }
+    }
+}
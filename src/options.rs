@@ -0,0 +1,184 @@
+use std::num::NonZeroUsize;
+
+use crate::command::{CommandArg, CommandArgError};
+
+/// A type that can be stored in [`EditorOptions`]
+pub trait OptionValue: Sized {
+    fn from_arg(arg: CommandArg) -> anyhow::Result<Self>;
+    fn to_arg(&self) -> CommandArg;
+}
+
+impl OptionValue for bool {
+    fn from_arg(arg: CommandArg) -> anyhow::Result<Self> {
+        Ok(arg.try_into()?)
+    }
+
+    fn to_arg(&self) -> CommandArg {
+        CommandArg::Bool(*self)
+    }
+}
+
+impl OptionValue for String {
+    fn from_arg(arg: CommandArg) -> anyhow::Result<Self> {
+        Ok(arg.into())
+    }
+
+    fn to_arg(&self) -> CommandArg {
+        CommandArg::String(self.clone())
+    }
+}
+
+impl OptionValue for usize {
+    fn from_arg(arg: CommandArg) -> anyhow::Result<Self> {
+        let value: i32 = arg.try_into()?;
+        value.try_into().map_err(|_| {
+            CommandArgError {
+                expected: "non-negative Integer".into(),
+                found: value.to_string(),
+            }
+            .into()
+        })
+    }
+
+    fn to_arg(&self) -> CommandArg {
+        CommandArg::Integer(*self as i32)
+    }
+}
+
+impl OptionValue for NonZeroUsize {
+    fn from_arg(arg: CommandArg) -> anyhow::Result<Self> {
+        let value: i32 = arg.try_into()?;
+        usize::try_from(value)
+            .ok()
+            .and_then(NonZeroUsize::new)
+            .ok_or_else(|| {
+                CommandArgError {
+                    expected: "positive Integer".into(),
+                    found: value.to_string(),
+                }
+                .into()
+            })
+    }
+
+    fn to_arg(&self) -> CommandArg {
+        CommandArg::Integer(self.get() as i32)
+    }
+}
+
+macro_rules! editor_options {
+    ($($(#[doc = $doc:literal])* $name:ident: $ty:ty = $default:expr,)*) => {
+        /// Editor settings, changed through `set-option` or `Editor.set_option`
+        #[derive(Clone)]
+        pub struct EditorOptions {
+            $(
+                $(#[doc = $doc])*
+                pub $name: $ty,
+            )*
+        }
+
+        impl Default for EditorOptions {
+            fn default() -> Self {
+                Self {
+                    $($name: $default,)*
+                }
+            }
+        }
+
+        impl EditorOptions {
+            pub fn set(&mut self, name: &str, value: CommandArg) -> anyhow::Result<()> {
+                match name {
+                    $(stringify!($name) => self.$name = OptionValue::from_arg(value)?,)*
+                    _ => anyhow::bail!("Unknown option {name}"),
+                }
+                Ok(())
+            }
+
+            pub fn get(&self, name: &str) -> anyhow::Result<CommandArg> {
+                match name {
+                    $(stringify!($name) => Ok(self.$name.to_arg()),)*
+                    _ => anyhow::bail!("Unknown option {name}"),
+                }
+            }
+
+            /// These options with `overrides` applied on top
+            pub fn with_overrides(&self, overrides: &OptionOverrides) -> Self {
+                Self {
+                    $($name: overrides.$name.clone().unwrap_or_else(|| self.$name.clone()),)*
+                }
+            }
+        }
+
+        /// Per-buffer options, taking precedence over the global ones where set
+        #[derive(Default)]
+        pub struct OptionOverrides {
+            $(pub $name: Option<$ty>,)*
+        }
+
+        impl OptionOverrides {
+            /// Sets an override, or removes it if `value` is `None`
+            pub fn set(&mut self, name: &str, value: Option<CommandArg>) -> anyhow::Result<()> {
+                match name {
+                    $(stringify!($name) => {
+                        self.$name = value.map(OptionValue::from_arg).transpose()?
+                    })*
+                    _ => anyhow::bail!("Unknown option {name}"),
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+editor_options! {
+    /// Width of one indentation level, in columns
+    indent_width: NonZeroUsize = NonZeroUsize::new(4).unwrap(),
+    /// Indent with tabs instead of spaces
+    use_tabs: bool = false,
+    /// Chars besides letters and digits that are part of words
+    word_chars: String = "_".into(),
+    /// Automatically insert the closing char of a pair in insert mode
+    auto_pairs: bool = true,
+    /// Remove whitespace at the end of lines when writing a buffer
+    trim_on_write: bool = false,
+    /// Render spaces and tabs as visible glyphs
+    show_whitespace: bool = false,
+    /// Render a glyph at the end of each line
+    show_eol: bool = false,
+    /// Render `~` on rows past the end of the buffer
+    show_eob: bool = false,
+    /// Show line numbers in a gutter left of the text
+    line_numbers: bool = false,
+    /// Highlight the line of the primary cursor
+    cursor_line: bool = false,
+    /// Lines kept visible above and below the primary cursor when scrolling
+    scrolloff: usize = 0,
+    /// Reserve a column left of the text for signs
+    sign_column: bool = true,
+    /// Mark whitespace at the end of lines, except where a cursor is typing
+    trailing_whitespace_lint: bool = true,
+    /// Highlight buffers on a background thread
+    background_highlighting: bool = true,
+    /// Files larger than this many bytes are opened without syntax highlighting
+    large_file_bytes: usize = 8 * 1024 * 1024,
+    /// Reload unmodified buffers when their file changes on disk
+    auto_reload: bool = false,
+    /// Mode to start in, e.g. `normal` or `insert`
+    initial_mode: String = "normal".into(),
+    /// Most columns the mode name may take in the status line, or 0 for no limit
+    status_mode_width: usize = 0,
+    /// Seconds before a message disappears from the command line, or 0 to keep it
+    message_timeout: usize = 5,
+    /// Most entries the kill ring keeps, dropping the oldest
+    kill_ring_max: usize = 100,
+}
+
+impl EditorOptions {
+    /// Text inserted for one level of indentation
+    pub fn indent_unit(&self) -> String {
+        if self.use_tabs {
+            "\t".into()
+        } else {
+            " ".repeat(self.indent_width.get())
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender, TryRecvError},
+    },
+    thread,
+};
+
+use anyhow::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProcessId(pub usize);
+
+impl ProcessId {
+    pub fn generate() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(1);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        Self(id)
+    }
+}
+
+/// A child process started from Lua. Its stdout is read line by line on a
+/// separate thread, and the main loop hands the lines to `on_output`. Lines
+/// sent to it are written on another thread, so a process that stops reading
+/// can't block the editor.
+pub struct Process {
+    child: Child,
+    input: Option<Sender<String>>,
+    output: Receiver<String>,
+    pub on_output: mlua::Function<'static>,
+}
+
+impl Process {
+    pub fn spawn(
+        cmd: &str,
+        args: &[String],
+        on_output: mlua::Function<'static>,
+    ) -> anyhow::Result<Self> {
+        let mut child = Command::new(cmd)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Could not start {cmd}"))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (input, lines) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            for line in lines {
+                if writeln!(stdin, "{line}")
+                    .and_then(|_| stdin.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            child,
+            input: Some(input),
+            output,
+            on_output,
+        })
+    }
+
+    /// Queues `line` and a newline to be written to the process's stdin
+    pub fn send(&mut self, line: &str) -> anyhow::Result<()> {
+        let input = self.input.as_ref().context("Process stdin is closed")?;
+        if input.send(line.to_string()).is_err() {
+            // The writer stopped, so the process no longer reads its stdin
+            self.input = None;
+            anyhow::bail!("Process stdin is closed");
+        }
+        Ok(())
+    }
+
+    /// Lines printed since the last call, and whether the output has ended
+    pub fn read(&mut self) -> (Vec<String>, bool) {
+        let mut lines = vec![];
+        loop {
+            match self.output.try_recv() {
+                Ok(line) => lines.push(line),
+                Err(TryRecvError::Empty) => return (lines, false),
+                Err(TryRecvError::Disconnected) => return (lines, true),
+            }
+        }
+    }
+
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
@@ -4,7 +4,7 @@ use ropey::Rope;
 
 use crate::{lua::GetEngine, view::ViewId};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Selection {
     pub view: ViewId,
     pub start: usize,
@@ -184,7 +184,7 @@ impl<'lua> FromLua<'lua> for Selection {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
     Forward,
     Back,
@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use ratatui::style::Color;
+
+/// Glyph shown in the sign column next to a line
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sign {
+    pub glyph: char,
+    pub color: Color,
+}
+
+/// Who placed a sign. Later groups win when several have a sign on the same line.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum SignGroup {
+    Git,
+    Lua,
+}
+
+/// Signs of a buffer by line, kept on their lines as text is edited
+#[derive(Default)]
+pub struct Signs {
+    groups: BTreeMap<SignGroup, BTreeMap<usize, Sign>>,
+}
+
+impl Signs {
+    pub fn set(&mut self, group: SignGroup, line: usize, sign: Sign) {
+        self.groups.entry(group).or_default().insert(line, sign);
+    }
+
+    pub fn remove(&mut self, group: SignGroup, line: usize) -> Option<Sign> {
+        self.groups.get_mut(&group)?.remove(&line)
+    }
+
+    pub fn clear(&mut self, group: SignGroup) {
+        self.groups.remove(&group);
+    }
+
+    /// The sign shown on `line`
+    pub fn get(&self, line: usize) -> Option<&Sign> {
+        self.groups
+            .values()
+            .rev()
+            .find_map(|lines| lines.get(&line))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.values().all(BTreeMap::is_empty)
+    }
+
+    /// Adjusts signs for `count` lines being inserted after `line`
+    pub fn insert_lines(&mut self, line: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        for lines in self.groups.values_mut() {
+            let moved = lines.split_off(&(line + 1));
+            lines.extend(moved.into_iter().map(|(l, sign)| (l + count, sign)));
+        }
+    }
+
+    /// Adjusts signs for the lines `start..=end` being joined into one
+    pub fn remove_lines(&mut self, start: usize, end: usize) {
+        let count = end - start;
+        if count == 0 {
+            return;
+        }
+        for lines in self.groups.values_mut() {
+            let mut moved = lines.split_off(&(start + 1));
+            let moved = moved.split_off(&(end + 1));
+            lines.extend(moved.into_iter().map(|(l, sign)| (l - count, sign)));
+        }
+    }
+}
@@ -1,18 +1,27 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    cell::RefMut,
+    collections::HashMap,
+    ops::{Deref, DerefMut, RangeInclusive},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use mlua::FromLua;
 use ratatui::{
+    layout::Rect,
     style::{Color, Modifier, Style, Styled, Stylize},
     text::ToText,
     widgets::Widget,
 };
-use ropey::Rope;
+use ropey::{Rope, RopeSlice};
 use tree_sitter_highlight::{HighlightConfiguration, HighlightEvent, Highlighter};
+use unicode_width::UnicodeWidthChar;
 
 use crate::{
-    buffer::{Buffer, BufferId},
+    buffer::{line_count, Buffer, BufferId, ViewEdit},
+    diff::DiffLine,
     engine::Size,
     mode::Mode,
+    options::EditorOptions,
     selection::Selection,
 };
 
@@ -46,6 +55,35 @@ pub struct View {
 
     /// Keep this sorted by start index pls
     pub selections: Vec<Selection>,
+    /// Index into `selections` of the primary selection, which the view follows
+    pub primary_index: usize,
+    /// Selections before each selection-only command, newest last
+    pub selection_history: Vec<SelectionSnapshot>,
+    /// Selections undone by `selection-undo`, newest last
+    pub selection_future: Vec<SelectionSnapshot>,
+
+    pub folds: Folds,
+
+    /// Refuse edits made through this view, while other views of the buffer
+    /// may still edit it
+    pub read_only: bool,
+
+    /// Views in the same group scroll together
+    pub scroll_lock_group: Option<usize>,
+    /// `vscroll` as of the last time it was synced with the lock group
+    pub locked_vscroll: usize,
+}
+
+/// How many selection changes `selection-undo` can step back through
+const SELECTION_HISTORY_LEN: usize = 100;
+
+/// Cells taken by the sign column: the glyph and a space
+const SIGN_COLUMN_WIDTH: u16 = 2;
+
+#[derive(Clone, PartialEq)]
+pub struct SelectionSnapshot {
+    pub selections: Vec<Selection>,
+    pub primary_index: usize,
 }
 
 impl View {
@@ -59,11 +97,118 @@ impl View {
             hscroll: 0,
             size,
             selections: vec![Selection::new(id)],
+            primary_index: 0,
+            selection_history: vec![],
+            selection_future: vec![],
+            folds: Folds::default(),
+            read_only: false,
+            scroll_lock_group: None,
+            locked_vscroll: 0,
+        }
+    }
+
+    /// Moves selections and folds to follow an edit to the buffer
+    pub fn apply_edit(&mut self, edit: &ViewEdit) {
+        match *edit {
+            ViewEdit::Insert {
+                start,
+                len,
+                line,
+                lines,
+            } => {
+                self.folds.insert_lines(line, lines);
+                for selection in &mut self.selections {
+                    if selection.start >= start {
+                        selection.start += len;
+                    }
+                    if selection.end >= start {
+                        selection.end += len;
+                    }
+                }
+            }
+            ViewEdit::Remove {
+                start,
+                len,
+                line_start,
+                line_end,
+            } => {
+                self.folds.remove_lines(line_start, line_end);
+                for selection in &mut self.selections {
+                    if selection.start >= start {
+                        selection.start = (selection.start.saturating_sub(len)).max(start);
+                    }
+                    if selection.end >= start {
+                        selection.end = (selection.end.saturating_sub(len)).max(start);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn primary_selection(&self) -> Option<&Selection> {
+        self.selections.get(self.primary_index)
+    }
+
+    /// Keeps `primary_index` in bounds after selections have been removed
+    pub fn clamp_primary(&mut self) {
+        self.primary_index = self
+            .primary_index
+            .min(self.selections.len().saturating_sub(1));
+    }
+
+    pub fn selection_snapshot(&self) -> SelectionSnapshot {
+        SelectionSnapshot {
+            selections: self.selections.clone(),
+            primary_index: self.primary_index,
+        }
+    }
+
+    /// Records selections from before a change so `selection-undo` can restore them
+    pub fn record_selections(&mut self, snapshot: SelectionSnapshot) {
+        if self.selection_history.len() == SELECTION_HISTORY_LEN {
+            self.selection_history.remove(0);
+        }
+        self.selection_history.push(snapshot);
+        self.selection_future.clear();
+    }
+
+    /// Restores the selections from before the last selection change. Returns
+    /// false if there is nothing to undo.
+    pub fn undo_selections(&mut self, text: &Rope) -> bool {
+        let Some(snapshot) = self.selection_history.pop() else {
+            return false;
+        };
+        self.selection_future.push(self.selection_snapshot());
+        self.restore_selections(snapshot, text);
+        true
+    }
+
+    /// Reapplies the last selection change undone by `undo_selections`
+    pub fn redo_selections(&mut self, text: &Rope) -> bool {
+        let Some(snapshot) = self.selection_future.pop() else {
+            return false;
+        };
+        self.selection_history.push(self.selection_snapshot());
+        self.restore_selections(snapshot, text);
+        true
+    }
+
+    fn restore_selections(&mut self, snapshot: SelectionSnapshot, text: &Rope) {
+        self.selections = snapshot.selections;
+        self.primary_index = snapshot.primary_index;
+        for sel in &mut self.selections {
+            sel.make_valid(text);
         }
+        self.merge_overlapping_selections();
     }
 
     pub fn sort_selections(&mut self) {
-        self.selections.sort_by_key(|s| s.start);
+        let mut order = (0..self.selections.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| self.selections[i].start);
+        if let Some(primary) = order.iter().position(|&i| i == self.primary_index) {
+            self.primary_index = primary;
+        }
+        self.selections = order.into_iter().map(|i| self.selections[i]).collect();
     }
 
     pub fn merge_overlapping_selections(&mut self) {
@@ -77,30 +222,315 @@ impl View {
                 a.start = a.start.min(s.start);
                 a.end = a.end.max(s.end);
                 self.selections.remove(cursor);
+                if self.primary_index == cursor {
+                    self.primary_index = active;
+                } else if self.primary_index > cursor {
+                    self.primary_index -= 1;
+                }
             } else {
                 active += 1;
                 cursor += 1;
             }
         }
+        self.clamp_primary();
     }
 
     pub fn resize(&mut self, size: Size) {
         self.size = size;
     }
 
-    pub fn make_selection_visisble(&mut self, buffer: &Buffer) {
-        let Some(primary) = self.selections.first() else {
+    /// Scrolls so the primary selection's head is shown with `scrolloff` lines
+    /// around it
+    pub fn make_selection_visisble(&mut self, buffer: &Buffer, scrolloff: usize) {
+        let Some(primary) = self.primary_selection() else {
+            return;
+        };
+        let head = primary.head().min(buffer.contents.len_chars());
+        let line = self.folds.visible_start(buffer.contents.char_to_line(head));
+
+        // A view too short for the margins on both sides keeps the head centered
+        let margin = scrolloff.min(self.size.height.saturating_sub(1) / 2);
+        let mut first = line;
+        for _ in 0..margin {
+            first = self.folds.prev_visible(first);
+        }
+        let mut last = line;
+        for _ in 0..margin {
+            let next = self.folds.next_visible(last);
+            if next >= line_count(&buffer.contents) {
+                break;
+            }
+            last = next;
+        }
+
+        self.vscroll = self.folds.visible_start(self.vscroll);
+
+        if first < self.vscroll {
+            self.vscroll = first;
+        }
+
+        // Walk upwards from the last line to keep visible; if we run out of
+        // screen rows before reaching the top of the view, it is below it
+        let mut top = last;
+        let mut rows = 1;
+        while top > self.vscroll && rows < self.size.height {
+            top = self.folds.prev_visible(top);
+            rows += 1;
+        }
+        if top > self.vscroll {
+            self.vscroll = top;
+        }
+    }
+
+    /// Scrolls so that the primary selection's head is in the middle of the view
+    pub fn center_selection(&mut self, buffer: &Buffer) {
+        let Some(primary) = self.primary_selection() else {
             return;
         };
-        let head = primary.head();
-        let line = buffer.contents.char_to_line(head);
+        let head = primary.head().min(buffer.contents.len_chars());
+        let mut top = self.folds.visible_start(buffer.contents.char_to_line(head));
+        for _ in 0..self.size.height / 2 {
+            top = self.folds.prev_visible(top);
+        }
+        self.vscroll = top;
+    }
+}
+
+/// The views of an engine, owned or borrowed however the caller has them
+pub trait Views {
+    fn views(&self) -> &HashMap<ViewId, View>;
+    fn views_mut(&mut self) -> &mut HashMap<ViewId, View>;
+}
+
+impl Views for HashMap<ViewId, View> {
+    fn views(&self) -> &HashMap<ViewId, View> {
+        self
+    }
+
+    fn views_mut(&mut self) -> &mut HashMap<ViewId, View> {
+        self
+    }
+}
+
+impl Views for &mut HashMap<ViewId, View> {
+    fn views(&self) -> &HashMap<ViewId, View> {
+        self
+    }
+
+    fn views_mut(&mut self) -> &mut HashMap<ViewId, View> {
+        self
+    }
+}
+
+impl Views for RefMut<'_, HashMap<ViewId, View>> {
+    fn views(&self) -> &HashMap<ViewId, View> {
+        self
+    }
+
+    fn views_mut(&mut self) -> &mut HashMap<ViewId, View> {
+        self
+    }
+}
+
+/// A view borrowed along with the others, so that edits made through it can
+/// move the selections and folds of every view of the buffer
+pub struct ViewMut<V> {
+    views: V,
+    id: ViewId,
+}
+
+impl<V: Views> ViewMut<V> {
+    pub fn new(views: V, id: ViewId) -> Self {
+        Self { views, id }
+    }
+
+    /// Moves the selections and folds of each view of `buffer` to follow an edit
+    pub fn apply_edit_to_views(&mut self, buffer: BufferId, edit: &ViewEdit) {
+        for view in self.views.views_mut().values_mut() {
+            if view.buffer == buffer {
+                view.apply_edit(edit);
+            }
+        }
+    }
+}
+
+impl<V: Views> Deref for ViewMut<V> {
+    type Target = View;
+
+    fn deref(&self) -> &View {
+        &self.views.views()[&self.id]
+    }
+}
+
+impl<V: Views> DerefMut for ViewMut<V> {
+    fn deref_mut(&mut self) -> &mut View {
+        self.views.views_mut().get_mut(&self.id).unwrap()
+    }
+}
+
+/// Folded line ranges. The first line of a fold stays visible as a summary,
+/// the rest are hidden.
+#[derive(Default, Clone)]
+pub struct Folds {
+    /// Sorted and non-overlapping
+    ranges: Vec<RangeInclusive<usize>>,
+}
+
+impl Folds {
+    pub fn add(&mut self, range: RangeInclusive<usize>) {
+        let mut start = *range.start();
+        let mut end = *range.end();
+        self.ranges.retain(|r| {
+            if *r.start() <= end && *r.end() >= start {
+                start = start.min(*r.start());
+                end = end.max(*r.end());
+                false
+            } else {
+                true
+            }
+        });
+        let index = self.ranges.partition_point(|r| *r.start() < start);
+        self.ranges.insert(index, start..=end);
+    }
+
+    /// Removes any folds containing the given line. Returns whether a fold was removed.
+    pub fn remove_containing(&mut self, line: usize) -> bool {
+        let len = self.ranges.len();
+        self.ranges.retain(|r| !r.contains(&line));
+        len != self.ranges.len()
+    }
+
+    pub fn get(&self, line: usize) -> Option<&RangeInclusive<usize>> {
+        self.ranges.iter().find(|r| *r.start() == line)
+    }
+
+    /// Returns the visible line representing the given line
+    pub fn visible_start(&self, line: usize) -> usize {
+        self.ranges
+            .iter()
+            .find(|r| *r.start() < line && line <= *r.end())
+            .map_or(line, |r| *r.start())
+    }
+
+    pub fn next_visible(&self, line: usize) -> usize {
+        self.ranges
+            .iter()
+            .find(|r| r.contains(&line))
+            .map_or(line, |r| *r.end())
+            + 1
+    }
+
+    pub fn prev_visible(&self, line: usize) -> usize {
+        self.visible_start(line.saturating_sub(1))
+    }
+
+    /// Adjusts folds for `count` lines inserted after `line`
+    pub fn insert_lines(&mut self, line: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        for r in &mut self.ranges {
+            if *r.start() > line {
+                *r = r.start() + count..=r.end() + count;
+            } else if *r.end() >= line {
+                *r = *r.start()..=r.end() + count;
+            }
+        }
+    }
+
+    /// Adjusts folds for the lines `start..=end` being joined into one
+    pub fn remove_lines(&mut self, start: usize, end: usize) {
+        let count = end - start;
+        if count == 0 {
+            return;
+        }
+        self.ranges.retain_mut(|r| {
+            if *r.end() < start {
+                true
+            } else if *r.start() > end {
+                *r = r.start() - count..=r.end() - count;
+                true
+            } else if *r.start() <= start && *r.end() >= end {
+                *r = *r.start()..=r.end() - count;
+                r.start() < r.end()
+            } else {
+                false
+            }
+        });
+    }
+}
+
+/// Glyphs used to render whitespace when `show_whitespace` is enabled
+pub struct WhitespaceChars {
+    pub space: char,
+    pub tab: char,
+    pub eol: char,
+    pub trailing: Color,
+}
+
+impl Default for WhitespaceChars {
+    fn default() -> Self {
+        let utf8 = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .into_iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|val| !val.is_empty())
+            .is_some_and(|val| {
+                let val = val.to_lowercase();
+                val.contains("utf-8") || val.contains("utf8")
+            });
 
-        if line < self.vscroll {
-            self.vscroll = line;
+        if utf8 {
+            Self {
+                space: '·',
+                tab: '→',
+                eol: '¬',
+                trailing: Color::Red,
+            }
+        } else {
+            Self {
+                space: '.',
+                tab: '>',
+                eol: '$',
+                trailing: Color::Red,
+            }
         }
+    }
+}
+
+pub struct SelectionColors {
+    pub selection: Color,
+    pub secondary_selection: Color,
+    /// Primary cursor color per mode
+    pub cursor: HashMap<Mode, Color>,
+    /// Cursor color for modes without an explicit entry in `cursor`
+    pub cursor_fallback: Color,
+    pub secondary_cursor: Color,
+    /// Cursor color in views other than the active one, or `None` to hide
+    /// their cursors
+    pub unfocused_cursor: Option<Color>,
+    /// Background of the primary cursor's line when the `cursor_line` option is set
+    pub cursor_line: Color,
+}
+
+impl SelectionColors {
+    pub fn cursor(&self, mode: &Mode) -> Color {
+        self.cursor
+            .get(mode)
+            .copied()
+            .unwrap_or(self.cursor_fallback)
+    }
+}
 
-        if line >= self.vscroll + self.size.height {
-            self.vscroll = line - self.size.height + 1;
+impl Default for SelectionColors {
+    fn default() -> Self {
+        Self {
+            selection: Color::DarkGray,
+            secondary_selection: Color::Indexed(236),
+            cursor: HashMap::from([(Mode::Normal, Color::White), (Mode::Insert, Color::Green)]),
+            cursor_fallback: Color::Yellow,
+            secondary_cursor: Color::Gray,
+            unfocused_cursor: Some(Color::Indexed(240)),
+            cursor_line: Color::Indexed(235),
         }
     }
 }
@@ -109,6 +539,13 @@ pub struct ViewWidget<'a> {
     pub view: &'a View,
     pub buffer: &'a Buffer,
     pub mode: &'a Mode,
+    pub options: &'a EditorOptions,
+    pub whitespace_chars: &'a WhitespaceChars,
+    pub colors: &'a SelectionColors,
+    /// Whether this is the active view, which gets the mode's cursor colors
+    pub focused: bool,
+    /// Lines to highlight as differing from the other side of a diff
+    pub diff: Option<&'a HashMap<usize, DiffLine>>,
 }
 
 impl<'a> Widget for ViewWidget<'a> {
@@ -120,120 +557,531 @@ impl<'a> Widget for ViewWidget<'a> {
         let buffer = self.buffer;
         let mode = self.mode;
 
-        let Some(lines) = buffer.contents.get_lines_at(view.vscroll) else {
+        // A trailing newline ends the last line rather than starting another,
+        // unless a cursor past it needs a line to be drawn on
+        let text = &buffer.contents;
+        let mut lines = line_count(text);
+        if lines < text.len_lines()
+            && view
+                .selections
+                .iter()
+                .any(|s| s.end.max(s.start) >= text.len_chars())
+        {
+            lines = text.len_lines();
+        }
+
+        // The buffer line shown on each screen row, skipping folded lines
+        let mut rows = vec![];
+        let mut line = view.folds.visible_start(view.vscroll);
+        while rows.len() < area.height as usize && line < lines {
+            rows.push(line);
+            line = view.folds.next_visible(line);
+        }
+        if self.options.show_eob {
+            for row in rows.len()..area.height as usize {
+                buf[(area.x, area.y + row as u16)]
+                    .set_char('~')
+                    .set_fg(Color::DarkGray);
+            }
+        }
+
+        let cursor_line = view
+            .primary_selection()
+            .map(|s| {
+                buffer
+                    .contents
+                    .char_to_line(s.head().min(buffer.contents.len_chars()))
+            })
+            .filter(|_| self.options.cursor_line);
+
+        // Reserved even without signs, so that text doesn't shift when one is set
+        let area = if self.options.sign_column {
+            let width = SIGN_COLUMN_WIDTH.min(area.width);
+            for (row, &line_idx) in rows.iter().enumerate() {
+                if let Some(sign) = buffer.signs.get(line_idx) {
+                    buf.set_stringn(
+                        area.x,
+                        area.y + row as u16,
+                        sign.glyph.to_string(),
+                        width as usize,
+                        Style::new().fg(sign.color),
+                    );
+                }
+            }
+            Rect {
+                x: area.x + width,
+                width: area.width - width,
+                ..area
+            }
+        } else {
+            area
+        };
+
+        let area = if self.options.line_numbers {
+            let digits = lines.to_string().len();
+            let gutter = (digits as u16 + 1).min(area.width);
+            for (row, &line_idx) in rows.iter().enumerate() {
+                let fg = if Some(line_idx) == cursor_line {
+                    Color::White
+                } else {
+                    Color::DarkGray
+                };
+                buf.set_stringn(
+                    area.x,
+                    area.y + row as u16,
+                    format!("{:>digits$} ", line_idx + 1),
+                    gutter as usize,
+                    Style::new().fg(fg),
+                );
+            }
+            Rect {
+                x: area.x + gutter,
+                width: area.width - gutter,
+                ..area
+            }
+        } else {
+            area
+        };
+        if area.width == 0 {
+            return;
+        }
+
+        let (Some(&first_line), Some(&last_line)) = (rows.first(), rows.last()) else {
             return;
         };
-        let lines = lines.take(area.height as usize);
-
-        let mut curr = buffer.contents.line_to_byte(view.vscroll);
-        for (row, line) in lines.enumerate() {
-            buf.set_string(0, row as _, line.to_string(), Style::new());
-            let rope_slice = line.to_string();
-            let mut iter = rope_slice.chars().enumerate();
-            for (col, char) in &mut iter {
-                if col >= area.width.into() {
-                    curr += char.len_utf8() + iter.map(|(_, c)| c.len_utf8()).sum::<usize>();
+        let row_of = |line: usize| rows.iter().position(|l| *l == line);
+
+        // Lines where a cursor is typing don't get their trailing whitespace marked
+        let typing_lines = match mode {
+            Mode::Insert => view
+                .selections
+                .iter()
+                .map(|s| {
+                    buffer
+                        .contents
+                        .char_to_line(s.head().min(buffer.contents.len_chars()))
+                })
+                .collect(),
+            _ => vec![],
+        };
+        // Screen cells of trailing whitespace per row
+        let mut trailing_cells = vec![];
+
+        for (row, &line_idx) in rows.iter().enumerate() {
+            let line = buffer.contents.line(line_idx).to_string();
+            let trailing_start = line.trim_end().chars().count();
+            let mut curr = buffer.contents.line_to_byte(line_idx);
+            let mut line_width = 0;
+            let mut trailing_x = None;
+            // Screen column of the next char; wide chars take two cells
+            let mut x = 0;
+            for (col, char) in line.chars().enumerate() {
+                if col == trailing_start {
+                    trailing_x = Some(x);
+                }
+                let width = char_width(char);
+                if width == 0 {
+                    // Combining marks join the glyph before them
+                    if x > 0 {
+                        let cell = &mut buf[(area.x + x as u16 - 1, area.y + row as u16)];
+                        let symbol = format!("{}{char}", cell.symbol());
+                        cell.set_symbol(&symbol);
+                    }
+                    curr += char.len_utf8();
+                    continue;
+                }
+                if x + width > area.width.into() {
                     break;
                 }
-                buf[(col as u16, row as u16)].fg = buffer.colors[curr];
+                let cell = &mut buf[(area.x + x as u16, area.y + row as u16)];
+                cell.set_char(if char.is_control() { ' ' } else { char });
+                cell.fg = buffer.colors.get(curr).copied().unwrap_or(Color::White);
+                for i in 1..width {
+                    buf[(area.x + (x + i) as u16, area.y + row as u16)].reset();
+                }
+                let cell = &mut buf[(area.x + x as u16, area.y + row as u16)];
                 curr += char.len_utf8();
+                x += width;
+                if char != '\n' {
+                    line_width = x;
+                } else if self.options.show_eol {
+                    cell.set_char(self.whitespace_chars.eol);
+                    cell.fg = Color::DarkGray;
+                }
+
+                if self.options.show_whitespace {
+                    let glyph = match char {
+                        ' ' => self.whitespace_chars.space,
+                        '\t' => self.whitespace_chars.tab,
+                        _ => continue,
+                    };
+                    cell.set_char(glyph);
+                    cell.fg = if col >= trailing_start {
+                        self.whitespace_chars.trailing
+                    } else {
+                        Color::DarkGray
+                    };
+                }
+            }
+
+            if let Some(start) = trailing_x
+                && start < line_width
+                && self.options.trailing_whitespace_lint
+                && !typing_lines.contains(&line_idx)
+            {
+                trailing_cells.push((row, start, line_width));
+            }
+
+            if let Some(fold) = view.folds.get(line_idx) {
+                let marker = format!(" ··· {} lines", fold.end() - fold.start());
+                buf.set_stringn(
+                    area.x + line_width as u16,
+                    area.y + row as u16,
+                    marker,
+                    (area.width as usize).saturating_sub(line_width),
+                    Style::new().fg(Color::DarkGray),
+                );
             }
         }
 
-        let text = &buffer.contents;
+        // Drawn before the selections so that they take precedence
+        if let Some(row) = cursor_line.and_then(row_of) {
+            let row = Rect {
+                y: area.y + row as u16,
+                height: 1,
+                ..area
+            };
+            buf.set_style(row, Style::new().bg(self.colors.cursor_line));
+        }
+
+        for (row, line_idx) in rows.iter().enumerate() {
+            if let Some(kind) = self.diff.and_then(|lines| lines.get(line_idx)) {
+                let row = Rect {
+                    y: area.y + row as u16,
+                    height: 1,
+                    ..area
+                };
+                buf.set_style(row, Style::new().bg(kind.color()));
+            }
+        }
+
+        for (row, start, end) in trailing_cells {
+            let cells = Rect {
+                x: area.x + start as u16,
+                y: area.y + row as u16,
+                width: (end - start) as u16,
+                height: 1,
+            };
+            // Whitespace glyphs would vanish against their own color
+            let style = Style::new()
+                .fg(Color::White)
+                .bg(self.whitespace_chars.trailing);
+            buf.set_style(cells, style);
+        }
 
-        for selection in &self.view.selections {
-            let start_char = selection.start;
+        for (i, selection) in self.view.selections.iter().enumerate() {
+            let primary = i == view.primary_index;
+            let selection_color = if primary {
+                self.colors.selection
+            } else {
+                self.colors.secondary_selection
+            };
+            // Selections of other views may lag behind edits until they are synced
+            let len = text.len_chars();
+            let start_char = selection.start.min(len);
             let start_line = text.char_to_line(start_char);
             let start_col = start_char - text.line_to_char(start_line);
 
-            let end_char = selection.end;
+            let end_char = selection.end.min(len);
             let end_line = text.char_to_line(end_char);
             let end_col = end_char - text.line_to_char(end_line);
 
-            if start_line < view.vscroll && end_line < view.vscroll
-                || start_line >= view.vscroll + area.height as usize
-                    && end_line >= view.vscroll + area.height as usize
+            if start_line < first_line && end_line < first_line
+                || start_line > last_line && end_line > last_line
             {
                 continue;
             }
 
-            let clamped_start_line = start_line.max(view.vscroll);
-            let clamped_end_line = end_line.min(view.vscroll + area.height as usize - 1);
+            let clamped_start_line = start_line.max(first_line);
+            let clamped_end_line = end_line.min(last_line);
 
             let clamped_start_col = if clamped_start_line == start_line {
-                start_col.max(view.hscroll)
+                start_col
             } else {
                 0
             };
             let clamped_end_col = if clamped_end_line == end_line {
-                end_col.min(view.hscroll + view.size.width - 1)
+                end_col
             } else {
                 usize::MAX
             };
 
             fn fill_range(
                 buf: &mut ratatui::buffer::Buffer,
+                area: Rect,
                 line: usize,
                 start: usize,
                 end: usize,
+                color: Color,
             ) {
-                for col in start..=end {
-                    buf[(col as u16, line as u16)].bg = Color::DarkGray;
+                for col in start..(end + 1).min(area.width as usize) {
+                    buf[(area.x + col as u16, area.y + line as u16)].bg = color;
                 }
             }
 
-            let mut fill_range = |line, start: usize, end: usize, last_line: bool| {
+            // Takes char columns and fills the screen cells of the chars in
+            // them. A position past the last char, such as the end of the
+            // buffer, only shows the cursor.
+            let mut fill_range = |line, start: usize, end: usize| {
+                let Some(row) = row_of(line) else {
+                    return;
+                };
+                let line = text.line(line);
+                let Some(max_col) = line.len_chars().checked_sub(1) else {
+                    return;
+                };
+                if start > max_col {
+                    return;
+                }
+                let end = end.min(max_col);
+                let start = display_col(line, start);
+                let end = display_col(line, end + 1).saturating_sub(1).max(start);
+                // Clip to the visible columns before making them relative to the view
+                let start = start.max(view.hscroll);
+                let end = end.min(view.hscroll + area.width as usize);
+                if start > end || start >= view.hscroll + area.width as usize {
+                    return;
+                }
                 fill_range(
                     buf,
-                    line - view.vscroll,
-                    start.min(text.line(line).len_chars().saturating_sub(if last_line {
-                        0
-                    } else {
-                        1
-                    })) - view.hscroll,
-                    end.min(text.line(line).len_chars().saturating_sub(if last_line {
-                        0
-                    } else {
-                        1
-                    })) - view.hscroll,
+                    area,
+                    row,
+                    start - view.hscroll,
+                    end - view.hscroll,
+                    selection_color,
                 )
             };
 
             if clamped_start_line == clamped_end_line {
-                fill_range(clamped_start_line, clamped_start_col, clamped_end_col, true);
+                fill_range(clamped_start_line, clamped_start_col, clamped_end_col);
             } else {
-                fill_range(clamped_start_line, clamped_start_col, usize::MAX, false);
+                fill_range(clamped_start_line, clamped_start_col, usize::MAX);
                 for line in clamped_start_line + 1..clamped_end_line {
-                    fill_range(line, 0, usize::MAX, false);
+                    fill_range(line, 0, usize::MAX);
                 }
-                fill_range(clamped_end_line, 0, clamped_end_col, true);
+                fill_range(clamped_end_line, 0, clamped_end_col);
             }
 
-            let head = selection.head();
+            // At the end of the buffer the cursor takes the cell after the last char
+            let head = selection.head().min(len);
             let head_line = text.char_to_line(head);
             let head_col = head - text.line_to_char(head_line);
+            let head_x = display_col(text.line(head_line), head_col);
+            let head_width = display_col(text.line(head_line), head_col + 1) - head_x;
 
-            if head_line < view.vscroll
-                || head_line >= view.vscroll + area.height as usize
-                || head_col < view.hscroll
-                || head_col >= view.hscroll + area.width as usize
-            {
+            let Some(head_row) = row_of(head_line) else {
+                continue;
+            };
+            if head_x < view.hscroll || head_x >= view.hscroll + area.width as usize {
                 continue;
             }
 
-            let cursor_color = match mode {
-                Mode::Normal => Color::White,
-                Mode::Insert => Color::Green,
-                _ => Color::Yellow,
+            let cursor_color = if !self.focused {
+                let Some(color) = self.colors.unfocused_cursor else {
+                    continue;
+                };
+                color
+            } else if primary {
+                self.colors.cursor(mode)
+            } else {
+                self.colors.secondary_cursor
             };
 
-            buf[(
-                (head_col - view.hscroll) as u16,
-                (head_line - view.vscroll) as u16,
-            )]
-                .set_fg(Color::Black)
-                .set_bg(cursor_color);
+            for x in head_x..(head_x + head_width.max(1)).min(view.hscroll + area.width as usize) {
+                buf[(area.x + (x - view.hscroll) as u16, area.y + head_row as u16)]
+                    .set_fg(Color::Black)
+                    .set_bg(cursor_color);
+            }
+        }
+    }
+}
+
+/// Number of screen cells `c` takes up. Control chars, including tabs and
+/// newlines, are drawn as a single cell.
+pub fn char_width(c: char) -> usize {
+    if c.is_control() {
+        1
+    } else {
+        c.width().unwrap_or(1)
+    }
+}
+
+/// Screen column of the char at `col` in `line`. Columns past the end of the
+/// line count one cell each.
+pub fn display_col(line: RopeSlice, col: usize) -> usize {
+    let len = line.len_chars();
+    line.chars().take(col).map(char_width).sum::<usize>() + col.saturating_sub(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::buffer::Buffer as ScreenBuffer;
+
+    use super::*;
+
+    /// Renders `text` in a `width` by `height` view with the given selections,
+    /// the first being primary. The sign column is off, so text starts at the
+    /// left edge.
+    fn render(
+        text: &str,
+        selections: &[(usize, usize)],
+        hscroll: usize,
+        width: u16,
+        height: u16,
+    ) -> ScreenBuffer {
+        let options = EditorOptions {
+            sign_column: false,
+            ..EditorOptions::default()
+        };
+        render_with(&options, text, selections, hscroll, width, height)
+    }
+
+    fn render_with(
+        options: &EditorOptions,
+        text: &str,
+        selections: &[(usize, usize)],
+        hscroll: usize,
+        width: u16,
+        height: u16,
+    ) -> ScreenBuffer {
+        let buffer = Buffer::create_without_syntax("test".into(), Rope::from(text));
+        let size = Size {
+            width: width.into(),
+            height: height.into(),
+        };
+        let mut view = View::new(buffer.id, size);
+        view.hscroll = hscroll;
+        view.selections = selections
+            .iter()
+            .map(|&(start, end)| Selection {
+                start,
+                end,
+                ..Selection::new(view.id)
+            })
+            .collect();
+
+        let area = Rect::new(0, 0, width, height);
+        let mut screen = ScreenBuffer::empty(area);
+        ViewWidget {
+            view: &view,
+            buffer: &buffer,
+            mode: &Mode::Normal,
+            options,
+            whitespace_chars: &WhitespaceChars::default(),
+            colors: &SelectionColors::default(),
+            focused: true,
+            diff: None,
         }
+        .render(area, &mut screen);
+        screen
+    }
+
+    /// Each row of the screen, with `#` for cells with a background color,
+    /// i.e. selected or under a cursor
+    fn highlighted(screen: &ScreenBuffer) -> Vec<String> {
+        (0..screen.area.height)
+            .map(|y| {
+                (0..screen.area.width)
+                    .map(|x| match screen[(x, y)].bg {
+                        Color::Reset => '.',
+                        _ => '#',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scrolloff_keeps_lines_around_the_head() {
+        let buffer = Buffer::create_without_syntax("test".into(), Rope::from("x\n".repeat(20)));
+        let size = Size {
+            width: 10,
+            height: 5,
+        };
+        let mut view = View::new(buffer.id, size);
+        let move_to_line = |view: &mut View, line: usize| {
+            view.selections[0].start = line * 2;
+            view.selections[0].end = line * 2;
+            view.make_selection_visisble(&buffer, 2);
+        };
+
+        // Two lines below line 5 end the view
+        move_to_line(&mut view, 5);
+        assert_eq!(view.vscroll, 3);
+        // And two lines above line 4 start it
+        move_to_line(&mut view, 4);
+        assert_eq!(view.vscroll, 2);
+    }
+
+    #[test]
+    fn sign_column_is_reserved_without_signs() {
+        let screen = render_with(&EditorOptions::default(), "ab\n", &[(0, 0)], 0, 4, 2);
+        assert_eq!(screen[(2, 0)].symbol(), "a");
+        assert_eq!(highlighted(&screen), ["..#.", "...."]);
+    }
+
+    #[test]
+    fn two_line_selection_covers_exactly_its_chars() {
+        // From `b` through `d`, including the first line's newline
+        let screen = render("ab\ncd\n", &[(1, 4)], 0, 4, 3);
+        assert_eq!(highlighted(&screen), [".##.", "##..", "...."]);
+    }
+
+    #[test]
+    fn three_line_selection_covers_exactly_its_chars() {
+        let screen = render("ab\ncd\nef\n", &[(1, 6)], 0, 4, 3);
+        assert_eq!(highlighted(&screen), [".##.", "###.", "#..."]);
+    }
+
+    #[test]
+    fn selection_ending_at_line_start_covers_one_char_of_it() {
+        // Ends on the `c` that starts the second line
+        let screen = render("ab\ncd\n", &[(1, 3)], 0, 4, 3);
+        assert_eq!(highlighted(&screen), [".##.", "#...", "...."]);
+    }
+
+    #[test]
+    fn selection_from_column_zero_is_clipped_when_scrolled_right() {
+        // Columns 3 through 5 of the selection are in view
+        let screen = render("abcdefgh\n", &[(0, 5)], 3, 4, 2);
+        assert_eq!(highlighted(&screen), ["###.", "...."]);
+    }
+
+    #[test]
+    fn lines_left_of_the_scrolled_view_are_not_highlighted() {
+        // The second line and the cursor on it are left of column 3
+        let screen = render("abcdefgh\nab\n", &[(0, 10)], 3, 4, 2);
+        assert_eq!(highlighted(&screen), ["####", "...."]);
+    }
+
+    #[test]
+    fn cursor_at_end_of_buffer_is_drawn_after_the_last_char() {
+        let screen = render("ab", &[(2, 2)], 0, 4, 2);
+        assert_eq!(highlighted(&screen), ["..#.", "...."]);
+        assert_eq!(screen[(2, 0)].bg, Color::White);
+
+        // Past a trailing newline, on the line it starts
+        let screen = render("ab\n", &[(3, 3)], 0, 4, 2);
+        assert_eq!(highlighted(&screen), ["....", "#..."]);
+
+        // Selections of other views may point past the end until synced
+        let screen = render("ab", &[(7, 9)], 0, 4, 2);
+        assert_eq!(highlighted(&screen), ["..#.", "...."]);
+    }
+
+    #[test]
+    fn cursor_at_end_of_full_width_line_is_not_drawn() {
+        let screen = render("abcd", &[(4, 4)], 0, 4, 2);
+        assert_eq!(highlighted(&screen), ["....", "...."]);
     }
 }